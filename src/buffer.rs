@@ -0,0 +1,173 @@
+use diff::DiffCache;
+use marks::Marks;
+use undo::History;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static GENERATIONS: AtomicUsize = AtomicUsize::new(1);
+
+// Bytes of a buffer. They get a new generation whenever they may change, the generations
+// of all buffers differ, so results kept for a generation stay valid until the next change.
+#[derive(Clone, Default, Debug)]
+pub struct Bytes {
+    bytes: Vec<u8>,
+    generation: usize,
+}
+
+impl Bytes {
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Bytes {
+        Bytes { bytes, generation: GENERATIONS.fetch_add(1, Ordering::Relaxed) }
+    }
+}
+
+impl Deref for Bytes {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Vec<u8> {
+        &self.bytes
+    }
+}
+
+impl DerefMut for Bytes {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        self.generation = GENERATIONS.fetch_add(1, Ordering::Relaxed);
+        &mut self.bytes
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+// An open file. The editor keeps the current buffer while it is current.
+#[derive(Default)]
+pub struct Buffer {
+    pub path: String,
+    pub buf: Bytes,
+    // Content of the file on disk, to show the modified bytes
+    pub original: Bytes,
+    // The modified bytes, found again only after buf or original changed
+    pub changes: DiffCache,
+    pub marks: Marks,
+    pub history: History,
+    // Cursor when the buffer was left
+    pub cursorpos: usize,
+}
+
+impl Buffer {
+    // Missing files are created
+    pub fn open(path: &str) -> Result<Buffer, String> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|why| format!("Could not open {}: {}", path, why))?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)
+            .map_err(|why| format!("Could not read {}: {}", path, why))?;
+        Ok(Buffer::loaded(path, buf))
+    }
+    // The file has to exist, like the file compared in diff mode
+    pub fn open_existing(path: &str) -> Result<Buffer, String> {
+        let buf = std::fs::read(path).map_err(|why| format!("Could not open {}: {}", path, why))?;
+        Ok(Buffer::loaded(path, buf))
+    }
+    fn loaded(path: &str, buf: Vec<u8>) -> Buffer {
+        let buf = Bytes::from(buf);
+        Buffer {
+            path: path.to_string(),
+            original: buf.clone(),
+            history: History::new(&buf),
+            buf,
+            ..Buffer::default()
+        }
+    }
+    pub fn is_modified(&self) -> bool {
+        self.buf != self.original
+    }
+}
+
+// The open files, numbered from 1 like in vim
+pub struct Buffers {
+    buffers: Vec<Buffer>,
+    current: usize,
+}
+
+impl Buffers {
+    // The first buffer is current, the editor keeps it
+    pub fn new() -> Buffers {
+        Buffers {
+            buffers: vec![Buffer::default()],
+            current: 0,
+        }
+    }
+    pub fn current(&self) -> usize {
+        self.current
+    }
+    pub fn count(&self) -> usize {
+        self.buffers.len()
+    }
+    // A buffer which is not current
+    pub fn get(&self, id: usize) -> &Buffer {
+        &self.buffers[id]
+    }
+    pub fn get_mut(&mut self, id: usize) -> &mut Buffer {
+        &mut self.buffers[id]
+    }
+    pub fn find(&self, path: &str) -> Option<usize> {
+        (0..self.count()).find(|&id| id != self.current && self.buffers[id].path == path)
+    }
+    // Returns the id of the new buffer
+    pub fn add(&mut self, buffer: Buffer) -> usize {
+        self.buffers.push(buffer);
+        self.buffers.len() - 1
+    }
+    // Stores the current buffer and hands out the buffer id, which becomes current
+    pub fn switch(&mut self, current: Buffer, id: usize) -> Buffer {
+        self.buffers[self.current] = current;
+        self.current = id;
+        mem::take(&mut self.buffers[id])
+    }
+    // First buffer with unsaved changes, besides the current one
+    pub fn unsaved(&self) -> Option<usize> {
+        (0..self.count()).find(|&id| id != self.current && self.buffers[id].is_modified())
+    }
+}
+
+#[test]
+fn switch_keeps_buffers() {
+    let mut buffers = Buffers::new();
+    let second = buffers.add(Buffer { path: "b".to_string(), buf: vec![0x01].into(), ..Buffer::default() });
+    let first = Buffer { path: "a".to_string(), ..Buffer::default() };
+    let current = buffers.switch(first, second);
+    assert_eq!(current.path, "b");
+    assert_eq!(buffers.current(), 1);
+    assert_eq!(buffers.find("a"), Some(0));
+    assert_eq!(buffers.find("b"), None);
+    assert_eq!(buffers.unsaved(), None);
+    let current = buffers.switch(current, 0);
+    assert_eq!(current.path, "a");
+    assert_eq!(buffers.unsaved(), Some(1));
+}
+#[test]
+fn changes_renew_generation() {
+    let mut bytes = Bytes::from(vec![0x00]);
+    let copy = bytes.clone();
+    assert_eq!(copy.generation(), bytes.generation());
+    assert_ne!(Bytes::from(vec![0x00]).generation(), bytes.generation());
+    bytes[0] = 0x01;
+    assert!(bytes.generation() > copy.generation());
+    assert!(bytes != copy);
+}
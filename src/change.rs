@@ -0,0 +1,528 @@
+use Cursorstate;
+use marks::Marks;
+use view::View;
+use std::cmp;
+use std::ops::Range;
+
+// Deletes the previous nibble or byte in insert mode
+pub const BACKSPACE: char = '\u{7f}';
+
+// A command which modified the buffer, recorded to be repeated with .
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    // r with the replacement key
+    Replace(char),
+    // x
+    Remove,
+    // Keys typed in insert mode, including backspaces
+    Insert(String),
+    // Keys typed in replace mode
+    Overwrite(String),
+    // Bytes written over the following bytes by :fill
+    Fill(Vec<u8>),
+    // Bytes inserted by :insert
+    InsertBytes(Vec<u8>),
+    // Yanked bytes put by p after the cursor, or by P before it
+    Put(Vec<u8>, bool),
+}
+
+impl Change {
+    // Applies the change count times at the cursor
+    pub fn apply(
+        &self,
+        buf: &mut Vec<u8>,
+        marks: &mut Marks,
+        cursorpos: &mut usize,
+        cstate: &mut Cursorstate,
+        view: &View,
+        count: usize,
+    ) {
+        match *self {
+            Change::Replace(key) => replace(buf, cursorpos, *cstate, key, count, view),
+            Change::Remove => remove(buf, marks, cursorpos, count),
+            Change::Insert(ref keys) => {
+                for _ in 0..count {
+                    let mut typed = 0..0;
+                    for key in keys.chars() {
+                        if key == BACKSPACE {
+                            insert_back(buf, marks, cursorpos, cstate, view, &mut typed);
+                        } else {
+                            insert_key(buf, marks, cursorpos, cstate, key, view, &mut typed);
+                        }
+                    }
+                }
+            }
+            Change::Overwrite(ref keys) => {
+                for _ in 0..count {
+                    for key in keys.chars() {
+                        overwrite_key(buf, cursorpos, cstate, key, view);
+                    }
+                }
+            }
+            Change::Fill(ref bytes) => {
+                for i in 0..count {
+                    fill(buf, *cursorpos + i * bytes.len(), bytes);
+                }
+            }
+            Change::InsertBytes(ref bytes) => {
+                if let Some(len) = inserted_len(bytes.len(), count, buf.len()) {
+                    buf.splice(*cursorpos..*cursorpos, repeat_pattern(bytes, len));
+                    marks.inserted(*cursorpos, len);
+                }
+            }
+            Change::Put(ref bytes, after) => {
+                if let Some(len) = inserted_len(bytes.len(), count, buf.len()) {
+                    // The cursor ends on the last put byte, like in vim
+                    let pos = if after { cmp::min(*cursorpos + 1, buf.len()) } else { *cursorpos };
+                    buf.splice(pos..pos, repeat_pattern(bytes, len));
+                    marks.inserted(pos, len);
+                    *cursorpos = (pos + len).saturating_sub(1);
+                }
+            }
+        }
+    }
+    // Whether the change can be applied count times without growing the buffer past MAXBUFLEN
+    pub fn fits(&self, buflen: usize, count: usize) -> bool {
+        match *self {
+            Change::InsertBytes(ref bytes) | Change::Put(ref bytes, _) => inserted_len(bytes.len(), count, buflen).is_some(),
+            _ => true,
+        }
+    }
+}
+
+// Repeats the pattern until len bytes are reached, the last copy may be cut
+pub fn repeat_pattern(pattern: &[u8], len: usize) -> Vec<u8> {
+    pattern.iter().cloned().cycle().take(len).collect()
+}
+
+// Largest buffer :insert, :resize, :append and puts make, a mistyped count must not take all memory
+pub const MAXBUFLEN: usize = 1 << 30;
+
+// Length of count copies of len bytes, None when the buffer would grow past MAXBUFLEN
+pub fn inserted_len(len: usize, count: usize, buflen: usize) -> Option<usize> {
+    count.checked_mul(len).filter(|&total| total <= MAXBUFLEN.saturating_sub(buflen))
+}
+
+// Overwrites the bytes at start, the buffer grows when they reach past the end
+pub fn overwrite_bytes(buf: &mut Vec<u8>, start: usize, bytes: &[u8]) {
+    let end = start + bytes.len();
+    if end > buf.len() {
+        buf.resize(end, 0);
+    }
+    buf[start..end].copy_from_slice(bytes);
+}
+
+// Overwrites the bytes at start, bytes past the end are dropped
+pub fn fill(buf: &mut [u8], start: usize, bytes: &[u8]) {
+    let end = cmp::min(start.saturating_add(bytes.len()), buf.len());
+    if start < end {
+        buf[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+}
+
+// Moves the cursor to the next digit of a cell, or to the next cell
+fn next_digit(view: &View, cursorpos: &mut usize, cstate: &mut Cursorstate, digit: usize) {
+    if digit + 1 < view.width() {
+        *cstate = Cursorstate::Digit(digit + 1);
+    } else {
+        *cursorpos += view.group;
+        *cstate = Cursorstate::Digit(0);
+    }
+}
+
+// Replaces the nibble, digit or byte under the cursor in count bytes or cells,
+// the cursor stays on the last replaced one.
+pub fn replace(buf: &mut Vec<u8>, cursorpos: &mut usize, cstate: Cursorstate, key: char, count: usize, view: &View) {
+    if let Cursorstate::Digit(digit) = cstate {
+        let remaining = buf.len().saturating_sub(*cursorpos).div_ceil(view.group);
+        let cells = cmp::max(cmp::min(count, remaining), 1);
+        for i in 0..cells {
+            let pos = *cursorpos + i * view.group;
+            if let Some(value) = view.with_digit(view.read(buf, pos), digit, key) {
+                view.write(buf, pos, value);
+            }
+        }
+        *cursorpos += (cells - 1) * view.group;
+        return;
+    }
+    if cstate == Cursorstate::Asciichar {
+        // Chars the encoding can not represent are ignored
+        if let Some(bytes) = view.encoding.encode(key) {
+            let count = cmp::max(cmp::min(count, buf.len().saturating_sub(*cursorpos) / bytes.len()), 1);
+            for i in 0..count {
+                overwrite_bytes(buf, *cursorpos + i * bytes.len(), &bytes);
+            }
+            *cursorpos += (count - 1) * bytes.len();
+        }
+        return;
+    }
+    // Replacing at the end appends a byte
+    let count = cmp::max(cmp::min(count, buf.len().saturating_sub(*cursorpos)), 1);
+    for pos in *cursorpos..*cursorpos + count {
+        if pos >= buf.len() {
+            buf.insert(pos, 0);
+        }
+        match cstate {
+            Cursorstate::Leftnibble => {
+                if let Some(c) = key.to_digit(16) {
+                    buf[pos] = buf[pos] & 0x0F | (c as u8) << 4;
+                }
+            }
+            Cursorstate::Rightnibble => {
+                if let Some(c) = key.to_digit(16) {
+                    buf[pos] = buf[pos] & 0xF0 | c as u8;
+                }
+            }
+            _ => (),
+        }
+    }
+    *cursorpos += count - 1;
+}
+
+// Removes count bytes at the cursor
+pub fn remove(buf: &mut Vec<u8>, marks: &mut Marks, cursorpos: &mut usize, count: usize) {
+    // check if in valid range
+    if *cursorpos < buf.len() {
+        let end = cmp::min(*cursorpos + count, buf.len());
+        buf.drain(*cursorpos..end);
+        marks.removed(*cursorpos, end - *cursorpos);
+    }
+    // always perform the movement if possible
+    if *cursorpos > 0 && *cursorpos >= buf.len() {
+        *cursorpos = buf.len().saturating_sub(1);
+    }
+}
+
+// The bytes typed in an insert session grow by len bytes inserted at pos.
+// Bytes inserted elsewhere start a new range.
+fn typed_inserted(typed: &mut Range<usize>, pos: usize, len: usize) {
+    if typed.start <= pos && pos <= typed.end {
+        typed.end += len;
+    } else {
+        *typed = pos..pos + len;
+    }
+}
+
+// Removes len bytes at pos, the marks and the typed bytes behind them move
+fn typed_removed(buf: &mut Vec<u8>, marks: &mut Marks, typed: &mut Range<usize>, pos: usize, len: usize) {
+    buf.drain(pos..pos + len);
+    marks.removed(pos, len);
+    let moved = |p: usize| if p >= pos + len { p - len } else { cmp::min(p, pos) };
+    *typed = moved(typed.start)..moved(typed.end);
+}
+
+// Inserts a nibble, digit or ascii char at the cursor, typed holds the bytes inserted in this session
+pub fn insert_key(
+    buf: &mut Vec<u8>,
+    marks: &mut Marks,
+    cursorpos: &mut usize,
+    cstate: &mut Cursorstate,
+    key: char,
+    view: &View,
+    typed: &mut Range<usize>,
+) {
+    match *cstate {
+        Cursorstate::Digit(digit) => {
+            // The first digit inserts a new cell
+            let value = if digit == 0 { 0 } else { view.read(buf, *cursorpos) };
+            if let Some(value) = view.with_digit(value, digit, key) {
+                if digit == 0 {
+                    buf.splice(*cursorpos..*cursorpos, vec![0; view.group]);
+                    marks.inserted(*cursorpos, view.group);
+                    typed_inserted(typed, *cursorpos, view.group);
+                }
+                view.write(buf, *cursorpos, value);
+                next_digit(view, cursorpos, cstate, digit);
+            }
+        }
+        Cursorstate::Leftnibble => {
+            if let Some(c) = key.to_digit(16) {
+                buf.insert(*cursorpos, (c as u8) << 4);
+                marks.inserted(*cursorpos, 1);
+                typed_inserted(typed, *cursorpos, 1);
+                *cstate = Cursorstate::Rightnibble;
+            }
+        }
+        Cursorstate::Rightnibble => {
+            if *cursorpos == buf.len() {
+                buf.insert(*cursorpos, 0);
+                typed_inserted(typed, *cursorpos, 1);
+            }
+            if let Some(c) = key.to_digit(16) {
+                buf[*cursorpos] = buf[*cursorpos] & 0xF0 | c as u8;
+                *cstate = Cursorstate::Leftnibble;
+                *cursorpos += 1;
+            }
+        }
+        Cursorstate::Asciichar => {
+            if let Some(bytes) = view.encoding.encode(key) {
+                buf.splice(*cursorpos..*cursorpos, bytes.iter().cloned());
+                marks.inserted(*cursorpos, bytes.len());
+                typed_inserted(typed, *cursorpos, bytes.len());
+                *cursorpos += bytes.len();
+            }
+        }
+    }
+}
+
+// Deletes the previous nibble, digit or byte in insert mode.
+// Only cells typed in this session lose a nibble or digit, other cells are removed as a whole.
+pub fn insert_back(
+    buf: &mut Vec<u8>,
+    marks: &mut Marks,
+    cursorpos: &mut usize,
+    cstate: &mut Cursorstate,
+    view: &View,
+    typed: &mut Range<usize>,
+) {
+    if *cstate == Cursorstate::Asciichar {
+        // The whole char before the cursor is removed
+        let start = view.encoding.prev_char(buf, *cursorpos);
+        typed_removed(buf, marks, typed, start, *cursorpos - start);
+        *cursorpos = start;
+        return;
+    }
+    let (cellsize, first) = match *cstate {
+        Cursorstate::Digit(_) => (view.group, Cursorstate::Digit(0)),
+        _ => (1, Cursorstate::Leftnibble),
+    };
+    if *cstate != first {
+        *cstate = first;
+        if typed.contains(cursorpos) {
+            // The cell was not completed, remove it
+            let len = cmp::min(cellsize, buf.len() - *cursorpos);
+            typed_removed(buf, marks, typed, *cursorpos, len);
+            return;
+        }
+    }
+    if *cursorpos < cellsize {
+        return;
+    }
+    *cursorpos -= cellsize;
+    if !typed.contains(cursorpos) {
+        typed_removed(buf, marks, typed, *cursorpos, cellsize);
+    } else if first == Cursorstate::Leftnibble {
+        // Keep the left nibble of the previous byte
+        buf[*cursorpos] &= 0xF0;
+        *cstate = Cursorstate::Rightnibble;
+    } else {
+        // Clear the last digit of the previous cell
+        let last = view.width() - 1;
+        let value = view.read(buf, *cursorpos);
+        if let Some(value) = view.with_digit(value, last, '0') {
+            view.write(buf, *cursorpos, value);
+        }
+        *cstate = Cursorstate::Digit(last);
+    }
+}
+
+// Overwrites the nibble, digit or byte under the cursor and advances, typing past the end appends.
+// Returns false for keys which are no digits in the hex view.
+pub fn overwrite_key(buf: &mut Vec<u8>, cursorpos: &mut usize, cstate: &mut Cursorstate, key: char, view: &View) -> bool {
+    if let Cursorstate::Digit(digit) = *cstate {
+        return match view.with_digit(view.read(buf, *cursorpos), digit, key) {
+            Some(value) => {
+                view.write(buf, *cursorpos, value);
+                next_digit(view, cursorpos, cstate, digit);
+                true
+            }
+            None => false,
+        };
+    }
+    if *cstate == Cursorstate::Asciichar {
+        return match view.encoding.encode(key) {
+            Some(bytes) => {
+                overwrite_bytes(buf, *cursorpos, &bytes);
+                *cursorpos += bytes.len();
+                true
+            }
+            None => false,
+        };
+    }
+    let value = match key.to_digit(16) {
+        Some(c) => c as u8,
+        None => return false,
+    };
+    if *cursorpos == buf.len() {
+        buf.push(0);
+    }
+    match *cstate {
+        Cursorstate::Leftnibble => {
+            buf[*cursorpos] = buf[*cursorpos] & 0x0F | value << 4;
+            *cstate = Cursorstate::Rightnibble;
+        }
+        Cursorstate::Rightnibble => {
+            buf[*cursorpos] = buf[*cursorpos] & 0xF0 | value;
+            *cstate = Cursorstate::Leftnibble;
+            *cursorpos += 1;
+        }
+        _ => (),
+    }
+    true
+}
+
+#[test]
+fn replace_nibble_with_count() {
+    let mut buf = vec![0x12, 0x34, 0x56];
+    let mut pos = 0;
+    replace(&mut buf, &mut pos, Cursorstate::Rightnibble, 'f', 5, &View::default());
+    assert_eq!(buf, [0x1F, 0x3F, 0x5F]);
+    assert_eq!(pos, 2);
+}
+#[test]
+fn remove_stops_at_end() {
+    let mut buf = vec![0x00, 0x11, 0x22, 0x33];
+    let mut marks = Marks::default();
+    let mut pos = 2;
+    remove(&mut buf, &mut marks, &mut pos, 5);
+    assert_eq!(buf, [0x00, 0x11]);
+    assert_eq!(pos, 1);
+}
+#[test]
+fn insert_repeated_with_backspace() {
+    let mut buf = vec![0xFF];
+    let mut marks = Marks::default();
+    let mut pos = 0;
+    let mut cstate = Cursorstate::Leftnibble;
+    let change = Change::Insert("ab\u{7f}c".to_string());
+    change.apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 2);
+    assert_eq!(buf, [0xAC, 0xAC, 0xFF]);
+    assert_eq!(pos, 2);
+}
+#[test]
+fn backspace_removes_bytes_not_typed() {
+    let mut buf = vec![0x11, 0x22, 0x33];
+    let mut marks = Marks::default();
+    let mut pos = 2;
+    let mut cstate = Cursorstate::Leftnibble;
+    let view = View::default();
+    let mut typed = 0..0;
+    insert_key(&mut buf, &mut marks, &mut pos, &mut cstate, 'a', &view, &mut typed);
+    insert_key(&mut buf, &mut marks, &mut pos, &mut cstate, 'b', &view, &mut typed);
+    assert_eq!((buf.clone(), typed.clone()), (vec![0x11, 0x22, 0xAB, 0x33], 2..3));
+    // The typed byte loses its right nibble, then the byte before it is removed
+    insert_back(&mut buf, &mut marks, &mut pos, &mut cstate, &view, &mut typed);
+    assert_eq!((buf.clone(), pos, cstate), (vec![0x11, 0x22, 0xA0, 0x33], 2, Cursorstate::Rightnibble));
+    insert_back(&mut buf, &mut marks, &mut pos, &mut cstate, &view, &mut typed);
+    insert_back(&mut buf, &mut marks, &mut pos, &mut cstate, &view, &mut typed);
+    assert_eq!((buf.clone(), pos, cstate), (vec![0x11, 0x33], 1, Cursorstate::Leftnibble));
+    // On the right nibble of an old byte, the byte before it is removed
+    cstate = Cursorstate::Rightnibble;
+    insert_back(&mut buf, &mut marks, &mut pos, &mut cstate, &view, &mut typed);
+    assert_eq!((buf, pos, cstate), (vec![0x33], 0, Cursorstate::Leftnibble));
+}
+#[test]
+fn overwrite_appends_at_end() {
+    let mut buf = vec![0x00, 0x11];
+    let mut marks = Marks::default();
+    let mut pos = 1;
+    let mut cstate = Cursorstate::Asciichar;
+    Change::Overwrite("AB".to_string()).apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 1);
+    assert_eq!(buf, [0x00, 0x41, 0x42]);
+    assert_eq!(pos, 3);
+}
+#[test]
+fn pattern_is_cut_at_len() {
+    assert_eq!(repeat_pattern(&[0xDE, 0xAD, 0xBE, 0xEF], 6), [0xDE, 0xAD, 0xBE, 0xEF, 0xDE, 0xAD]);
+    assert_eq!(repeat_pattern(&[0xFF], 0), []);
+}
+#[test]
+fn overwrite_grows_buffer() {
+    let mut buf = vec![0x00, 0x11, 0x22];
+    overwrite_bytes(&mut buf, 1, &[0xAA, 0xBB]);
+    assert_eq!(buf, [0x00, 0xAA, 0xBB]);
+    overwrite_bytes(&mut buf, 2, &[0xCC, 0xDD]);
+    assert_eq!(buf, [0x00, 0xAA, 0xCC, 0xDD]);
+}
+#[test]
+fn fill_stops_at_end() {
+    let mut buf = vec![0x00, 0x11, 0x22];
+    fill(&mut buf, 2, &[0xCC, 0xDD]);
+    assert_eq!(buf, [0x00, 0x11, 0xCC]);
+    fill(&mut buf, 3, &[0xEE]);
+    assert_eq!(buf, [0x00, 0x11, 0xCC]);
+}
+#[test]
+fn repeated_fill_and_insert() {
+    let mut buf = vec![0x00; 6];
+    let mut marks = Marks::default();
+    let mut pos = 1;
+    let mut cstate = Cursorstate::Leftnibble;
+    Change::Fill(vec![0xFF, 0xEE]).apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 2);
+    assert_eq!(buf, [0x00, 0xFF, 0xEE, 0xFF, 0xEE, 0x00]);
+    Change::InsertBytes(vec![0x12]).apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 2);
+    assert_eq!(buf, [0x00, 0x12, 0x12, 0xFF, 0xEE, 0xFF, 0xEE, 0x00]);
+}
+#[test]
+fn put_after_and_before() {
+    let mut buf = vec![0x00, 0x11];
+    let mut marks = Marks::default();
+    let mut pos = 0;
+    let mut cstate = Cursorstate::Leftnibble;
+    Change::Put(vec![0xAA, 0xBB], true).apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 2);
+    assert_eq!(buf, [0x00, 0xAA, 0xBB, 0xAA, 0xBB, 0x11]);
+    assert_eq!(pos, 4);
+    Change::Put(vec![0xCC], false).apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 1);
+    assert_eq!(buf, [0x00, 0xAA, 0xBB, 0xAA, 0xCC, 0xBB, 0x11]);
+    assert_eq!(pos, 4);
+}
+#[test]
+fn edit_digits_of_cells() {
+    use view::Radix;
+    let view = View { radix: Radix::Dec, group: 2, bigendian: true, ..View::default() };
+    let mut buf = vec![0x00, 0x00];
+    let mut marks = Marks::default();
+    let mut pos = 0;
+    let mut cstate = Cursorstate::Digit(4);
+    replace(&mut buf, &mut pos, cstate, '7', 1, &view);
+    assert_eq!(buf, [0x00, 0x07]);
+    cstate = Cursorstate::Digit(0);
+    let mut typed = 0..0;
+    for key in "00258".chars() {
+        insert_key(&mut buf, &mut marks, &mut pos, &mut cstate, key, &view, &mut typed);
+    }
+    assert_eq!(buf, [0x01, 0x02, 0x00, 0x07]);
+    assert_eq!((pos, cstate, typed.clone()), (2, Cursorstate::Digit(0), 0..2));
+    insert_back(&mut buf, &mut marks, &mut pos, &mut cstate, &view, &mut typed);
+    assert_eq!(buf, [0x00, 0xFA, 0x00, 0x07]);
+    assert_eq!((pos, cstate), (0, Cursorstate::Digit(4)));
+    assert!(!overwrite_key(&mut buf, &mut pos, &mut cstate, 'x', &view));
+}
+#[test]
+fn type_chars_through_encoding() {
+    use encoding::Encoding;
+    let mut buf = vec![];
+    let mut marks = Marks::default();
+    let mut pos = 0;
+    let mut cstate = Cursorstate::Asciichar;
+    let mut view = View { encoding: Encoding::Utf16le, ..View::default() };
+    let mut typed = 0..0;
+    insert_key(&mut buf, &mut marks, &mut pos, &mut cstate, 'A', &view, &mut typed);
+    insert_key(&mut buf, &mut marks, &mut pos, &mut cstate, 'é', &view, &mut typed);
+    assert_eq!(buf, [0x41, 0x00, 0xE9, 0x00]);
+    insert_back(&mut buf, &mut marks, &mut pos, &mut cstate, &view, &mut typed);
+    assert_eq!((buf.clone(), pos), (vec![0x41, 0x00], 2));
+    view.encoding = Encoding::Cp037;
+    replace(&mut buf, &mut pos, cstate, 'A', 1, &view);
+    assert_eq!((buf.clone(), pos), (vec![0x41, 0x00, 0xC1], 2));
+    view.encoding = Encoding::Utf8;
+    assert!(overwrite_key(&mut buf, &mut pos, &mut cstate, '€', &view));
+    view.encoding = Encoding::Ascii;
+    assert!(!overwrite_key(&mut buf, &mut pos, &mut cstate, '€', &view));
+    assert_eq!(buf, [0x41, 0x00, 0xE2, 0x82, 0xAC]);
+}
+#[test]
+fn puts_stop_at_maxbuflen() {
+    let mut buf = vec![0x00];
+    let mut marks = Marks::default();
+    let (mut pos, mut cstate) = (0, Cursorstate::Leftnibble);
+    let put = Change::Put(vec![0xAA, 0xBB], true);
+    assert!(put.fits(buf.len(), 3));
+    assert!(!put.fits(buf.len(), MAXBUFLEN / 2));
+    assert!(!Change::InsertBytes(vec![0x12]).fits(buf.len(), usize::MAX));
+    put.apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), usize::MAX);
+    assert_eq!(buf, [0x00]);
+    put.apply(&mut buf, &mut marks, &mut pos, &mut cstate, &View::default(), 3);
+    assert_eq!(buf, [0x00, 0xAA, 0xBB, 0xAA, 0xBB, 0xAA, 0xBB]);
+    assert_eq!(pos, 6);
+}
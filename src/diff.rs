@@ -0,0 +1,289 @@
+use buffer::Bytes;
+use modified::Hunk;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+
+// Bytes which have to match on both sides to align them again
+const ANCHOR: usize = 8;
+// How far a match is searched after a difference
+const WINDOW: usize = 4096;
+
+// alen bytes at a differ from blen bytes at b, one side is empty for inserted bytes
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DiffHunk {
+    pub a: usize,
+    pub alen: usize,
+    pub b: usize,
+    pub blen: usize,
+}
+
+impl DiffHunk {
+    // The same difference seen from b
+    pub fn swap(self) -> DiffHunk {
+        DiffHunk {
+            a: self.b,
+            alen: self.blen,
+            b: self.a,
+            blen: self.alen,
+        }
+    }
+}
+
+// The sides match at x and y for the next ANCHOR bytes, or up to the end of the shorter side
+fn aligned(a: &[u8], b: &[u8], x: usize, y: usize) -> bool {
+    let len = cmp::min(ANCHOR, cmp::min(a.len() - x, b.len() - y));
+    len > 0 && a[x..x + len] == b[y..y + len]
+}
+
+// The positions of each sequence of ANCHOR bytes on side b, within WINDOW after a difference.
+// The window only moves forward, so every position is added and dropped once per diff.
+struct Anchors<'a> {
+    b: &'a [u8],
+    starts: HashMap<&'a [u8], VecDeque<usize>>,
+    // The positions from..end are in starts
+    from: usize,
+    end: usize,
+}
+
+impl<'a> Anchors<'a> {
+    fn new(b: &'a [u8]) -> Anchors<'a> {
+        Anchors { b, starts: HashMap::new(), from: 0, end: 0 }
+    }
+    // Shorter at the end of b
+    fn sequence(&self, y: usize) -> &'a [u8] {
+        &self.b[y..cmp::min(y + ANCHOR, self.b.len())]
+    }
+    // Moves the window to the positions y..=y+WINDOW
+    fn slide(&mut self, y: usize) {
+        for old in self.from..cmp::min(y, self.end) {
+            let sequence = self.sequence(old);
+            let positions = self.starts.get_mut(sequence).unwrap();
+            positions.pop_front();
+            if positions.is_empty() {
+                self.starts.remove(sequence);
+            }
+        }
+        self.from = y;
+        let end = cmp::min(y + WINDOW, self.b.len()) + 1;
+        for new in cmp::max(self.end, y)..end {
+            let sequence = self.sequence(new);
+            self.starts.entry(sequence).or_default().push_back(new);
+        }
+        self.end = cmp::max(self.end, end);
+    }
+    // The first position in the window where b continues like the sequence
+    fn find(&self, sequence: &[u8]) -> Option<usize> {
+        self.starts.get(sequence).and_then(|positions| positions.front().cloned())
+    }
+}
+
+// Finds where the sides match again after a difference at x and y.
+// Changed bytes mostly stay in place, so the same distance on both sides is tried first.
+// Otherwise the nearest match realigns after inserted or removed bytes.
+fn resync(a: &[u8], b: &[u8], x: usize, y: usize, anchors: &mut Anchors) -> (usize, usize) {
+    let shortest = cmp::min(a.len() - x, b.len() - y);
+    if let Some(k) = (1..=cmp::min(ANCHOR, shortest)).find(|&k| aligned(a, b, x + k, y + k)) {
+        return (k, k);
+    }
+    anchors.slide(y);
+    let mut best: Option<(usize, usize)> = None;
+    for i in 0..=cmp::min(WINDOW, a.len() - x) {
+        if best.is_some_and(|(besti, bestj)| besti + bestj <= i) {
+            break;
+        }
+        if let Some(j) = anchors.find(&a[x + i..cmp::min(x + i + ANCHOR, a.len())]).map(|pos| pos - y) {
+            if best.is_none_or(|(besti, bestj)| i + j < besti + bestj) {
+                best = Some((i, j));
+            }
+        }
+    }
+    // Nothing matches nearby, compare the next window by position
+    best.unwrap_or((cmp::min(WINDOW, a.len() - x), cmp::min(WINDOW, b.len() - y)))
+}
+
+// Range of the bytes which differ, in the old and the new buffer.
+// Everything before and after it is equal.
+pub fn changed_range(old: &[u8], new: &[u8]) -> (usize, usize, usize) {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let maxsuffix = cmp::min(old.len(), new.len()) - prefix;
+    let suffix = old.iter().rev().zip(new.iter().rev()).take(maxsuffix).take_while(|&(a, b)| a == b).count();
+    (prefix, old.len() - suffix, new.len() - suffix)
+}
+
+// Differences of two buffers, sorted
+pub fn diff(a: &[u8], b: &[u8]) -> Vec<DiffHunk> {
+    // Only the bytes between the equal start and end are compared
+    let (start, aend, bend) = changed_range(a, b);
+    let (a, b) = (&a[..aend], &b[..bend]);
+    let mut anchors = Anchors::new(b);
+    let mut hunks: Vec<DiffHunk> = vec![];
+    let (mut x, mut y) = (start, start);
+    loop {
+        let same = a[x..].iter().zip(&b[y..]).take_while(|&(c, d)| c == d).count();
+        x += same;
+        y += same;
+        if x == a.len() && y == b.len() {
+            return hunks;
+        }
+        let (alen, blen) = resync(a, b, x, y, &mut anchors);
+        if alen == blen {
+            // Replaced bytes are compared by position, equal bytes in between are no difference
+            for i in (0..alen).filter(|&i| a[x + i] != b[y + i]) {
+                push(&mut hunks, DiffHunk { a: x + i, alen: 1, b: y + i, blen: 1 });
+            }
+        } else {
+            push(&mut hunks, DiffHunk { a: x, alen, b: y, blen });
+        }
+        x += alen;
+        y += blen;
+    }
+}
+
+// Adjacent differences are joined
+fn push(hunks: &mut Vec<DiffHunk>, new: DiffHunk) {
+    match hunks.last_mut() {
+        Some(ref mut hunk) if hunk.a + hunk.alen == new.a && hunk.b + hunk.blen == new.b => {
+            hunk.alen += new.alen;
+            hunk.blen += new.blen;
+        }
+        _ => hunks.push(new),
+    }
+}
+
+// Differences of two buffers, found again only after one of them changed
+#[derive(Default)]
+pub struct DiffCache {
+    generations: Option<(usize, usize)>,
+    hunks: Vec<DiffHunk>,
+}
+
+impl DiffCache {
+    pub fn diff(&mut self, a: &Bytes, b: &Bytes) -> &[DiffHunk] {
+        let generations = Some((a.generation(), b.generation()));
+        if self.generations != generations {
+            self.hunks = diff(a, b);
+            self.generations = generations;
+        }
+        &self.hunks
+    }
+}
+
+// The differences of side a, to be shown like modified bytes
+pub fn side_hunks(hunks: &[DiffHunk]) -> Vec<Hunk> {
+    hunks.iter().map(|hunk| Hunk { start: hunk.a, len: hunk.alen, origlen: hunk.blen }).collect()
+}
+
+// Position on side b of the byte at pos on side a
+pub fn map_position(hunks: &[DiffHunk], pos: usize) -> usize {
+    let mut mapped = pos;
+    for hunk in hunks {
+        if pos < hunk.a {
+            break;
+        }
+        if pos < hunk.a + hunk.alen {
+            // Inside a difference, as far into the other side as it reaches
+            return hunk.b + cmp::min(pos - hunk.a, hunk.blen.saturating_sub(1));
+        }
+        mapped = pos - (hunk.a + hunk.alen) + hunk.b + hunk.blen;
+    }
+    mapped
+}
+
+// The difference under the cursor, also where bytes are missing on side a
+pub fn hunk_at(hunks: &[DiffHunk], pos: usize) -> Option<DiffHunk> {
+    hunks.iter().find(|hunk| hunk.a <= pos && (pos < hunk.a + hunk.alen || hunk.a == pos)).cloned()
+}
+
+// Lines like cmp -l, the offsets in both files and the values of each differing byte in hex.
+// A byte missing on one side is shown as --, at the offset where it would be.
+// With ranges each difference is one line with its offsets and lengths.
+pub fn report(a: &[u8], b: &[u8], hunks: &[DiffHunk], ranges: bool) -> Vec<String> {
+    let mut lines = vec![];
+    for hunk in hunks {
+        if ranges {
+            lines.push(format!("{:08X}+{} {:08X}+{}", hunk.a, hunk.alen, hunk.b, hunk.blen));
+            continue;
+        }
+        for i in 0..cmp::max(hunk.alen, hunk.blen) {
+            let x = hunk.a + cmp::min(i, hunk.alen);
+            let y = hunk.b + cmp::min(i, hunk.blen);
+            let value = |buf: &[u8], pos: usize, len: usize| match i < len {
+                true => format!("{:02X}", buf[pos]),
+                false => "--".to_string(),
+            };
+            lines.push(format!("{:08X} {:08X} {} {}", x, y, value(a, x, hunk.alen), value(b, y, hunk.blen)));
+        }
+    }
+    lines
+}
+
+#[test]
+fn range_between_equal_ends() {
+    assert_eq!(changed_range(&[1, 2, 3, 4], &[1, 9, 9, 9, 4]), (1, 3, 4));
+    assert_eq!(changed_range(&[1, 1], &[1, 1, 1]), (2, 2, 3));
+    assert_eq!(changed_range(&[5], &[5]), (1, 1, 1));
+}
+#[test]
+fn changed_bytes_stay_in_place() {
+    let a = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    let b = [0x00, 0xFF, 0x22, 0xEE, 0xEE, 0x55];
+    assert_eq!(diff(&a, &b), [
+        DiffHunk { a: 1, alen: 1, b: 1, blen: 1 },
+        DiffHunk { a: 3, alen: 2, b: 3, blen: 2 },
+    ]);
+}
+#[test]
+fn inserted_bytes_realign() {
+    let a: Vec<u8> = (0..64).collect();
+    let mut b = a.clone();
+    b.splice(10..10, vec![0xAA; 5]);
+    b.remove(40);
+    let hunks = diff(&a, &b);
+    assert_eq!(hunks, [
+        DiffHunk { a: 10, alen: 0, b: 10, blen: 5 },
+        DiffHunk { a: 35, alen: 1, b: 40, blen: 0 },
+    ]);
+    assert_eq!(map_position(&hunks, 20), 25);
+    assert_eq!(map_position(&hunks, 50), 54);
+    assert_eq!(hunk_at(&hunks, 10), Some(hunks[0]));
+    assert_eq!(hunk_at(&hunks, 11), None);
+    assert_eq!(side_hunks(&hunks)[1], Hunk { start: 35, len: 1, origlen: 0 });
+}
+#[test]
+fn different_lengths_at_end() {
+    assert_eq!(diff(&[1, 2, 3], &[1, 2, 3, 4, 5]), [DiffHunk { a: 3, alen: 0, b: 3, blen: 2 }]);
+    assert_eq!(diff(&[9, 1, 2], &[1, 2]), [DiffHunk { a: 0, alen: 1, b: 0, blen: 0 }]);
+    assert_eq!(diff(&[], &[]), []);
+}
+#[test]
+fn changes_near_the_end_realign() {
+    let a = [0x00, 0x11, 0x22, 0x33];
+    let b = [0x00, 0xFF, 0x22, 0x33, 0x44, 0x55];
+    assert_eq!(diff(&a, &b), [
+        DiffHunk { a: 1, alen: 1, b: 1, blen: 1 },
+        DiffHunk { a: 4, alen: 0, b: 4, blen: 2 },
+    ]);
+}
+#[test]
+fn cache_follows_changes() {
+    let mut cache = DiffCache::default();
+    let mut a = Bytes::from(vec![1, 2]);
+    let b = Bytes::from(vec![1, 2]);
+    assert_eq!(cache.diff(&a, &b), []);
+    a[1] = 3;
+    assert_eq!(cache.diff(&a, &b), [DiffHunk { a: 1, alen: 1, b: 1, blen: 1 }]);
+    a[1] = 2;
+    assert_eq!(cache.diff(&a, &b), []);
+}
+#[test]
+fn report_bytes_and_ranges() {
+    let a = [0x00, 0x11, 0x22, 0x33];
+    let b = [0x00, 0xFF, 0x22, 0x33, 0x44, 0x55];
+    let hunks = diff(&a, &b);
+    assert_eq!(report(&a, &b, &hunks, false), [
+        "00000001 00000001 11 FF",
+        "00000004 00000004 -- 44",
+        "00000004 00000005 -- 55",
+    ]);
+    assert_eq!(report(&a, &b, &hunks, true), ["00000001+1 00000001+1", "00000004+0 00000004+2"]);
+}
@@ -1,44 +1,67 @@
 extern crate ncurses;
 use ncurses::*;
 use Cursorstate;
+use marks::Marks;
+use settings::Settings;
+use window::Region;
+use modified::{is_modified, Hunk};
 use std::cmp;
 
+#[allow(clippy::too_many_arguments)]
 pub fn draw(
-    buf: &[u8],
+    wholebuf: &[u8],
+    region: &Region,
     cursorpos: usize,
     cols: usize,
-    command: &String,
-    debug: &mut String,
     cstate: Cursorstate,
     screenoffset: usize,
+    highlight: Option<(usize, usize)>,
+    marks: &Marks,
+    settings: &Settings,
+    modified: &[Hunk],
 ) {
-    erase();
-
-    debug.clear();
-//    let screensize = get_screen_size(cols);
-//    debug.push_str(&format!("   screensize:{:?}", screensize));
-//    debug.push_str(&format!("   screenoffset:{:?}", screenoffset));
-//    debug.push_str(&format!("   buf.len():{:?}", buf.len()));
-
-    let screenheight: usize;
-    screenheight = getmaxy(stdscr()) as usize;
-//    debug.push_str(&format!("   screenheight:{:?}", screenheight));
-
+    let (start, end) = get_absolute_draw_indices(wholebuf.len(), cols, screenoffset, region.rows);
+    let buf = &wholebuf[start..end];
     let mut tmpbuflen = buf.len();
     if tmpbuflen >= 1 { tmpbuflen -= 1; }
     let rows = tmpbuflen / cols + 1;
 
-//    debug.push_str(&format!("   rows:{:?}", rows));
-
     for z in 0..rows {
-        // 8 hex digits (4GB/cols or 0.25GB@cols=SPALTEN)
-        printw(&format!("{:08X}: ", get_absolute_line(cols, screenoffset, z)));
+        mv((region.top + z) as i32, region.left as i32);
+        if !marks.is_empty() {
+            // Gutter with the first mark of the line
+            let line = get_absolute_line(cols, screenoffset, z);
+            match marks.find_in(line, line + cols) {
+                Some(name) => {
+                    attron(A_BOLD());
+                    printw(&format!("{} ", name));
+                    attroff(A_BOLD());
+                }
+                None => {
+                    printw("  ");
+                }
+            }
+        }
+        // The offset column grows with the file
+        let offset = settings.view.format_offset(get_absolute_line(cols, screenoffset, z), wholebuf.len());
+        printw(&format!("{}: ", offset));
         // Additional space between line number and hex
         printw(" ");
         for s in 0..cols {
+            if !settings.view.is_plain() {
+                if s.is_multiple_of(settings.view.group) {
+                    draw_cell(buf, z*cols + s, cursorpos, cstate, cols*screenoffset, highlight, settings, modified);
+                }
+                continue;
+            }
             let pos: usize = z*cols + s;
+            let highlighted = is_highlighted(highlight, pos+cols*screenoffset);
             if pos < buf.len() {
+                let classcolor = get_class_color(settings, buf[pos], pos+cols*screenoffset == cursorpos,
+                                                 is_modified(modified, pos+cols*screenoffset));
 
+                class_color_cond(true, classcolor);
+                highlight_cond(true, highlighted);
                 color_left_nibble_cond(true, pos+cols*screenoffset == cursorpos, cstate);
                 printw(&format!("{:01X}", buf[pos] >> 4));
                 color_left_nibble_cond(false, pos+cols*screenoffset == cursorpos, cstate);
@@ -46,6 +69,8 @@ pub fn draw(
                 color_right_nibble_cond(true, pos+cols*screenoffset == cursorpos, cstate);
                 printw(&format!("{:01X}", buf[pos] & 0x0F));
                 color_right_nibble_cond(false, pos+cols*screenoffset == cursorpos, cstate);
+                highlight_cond(false, highlighted);
+                class_color_cond(false, classcolor);
 
                 printw(" ");
             } else if pos == buf.len() {
@@ -67,18 +92,27 @@ pub fn draw(
         printw(" ");
         for s in 0..cols {
             let pos: usize = z*cols + s;
+            let highlighted = is_highlighted(highlight, pos+cols*screenoffset) && pos < buf.len();
+            let classcolor = match buf.get(pos) {
+                Some(&c) => get_class_color(settings, c, pos+cols*screenoffset == cursorpos,
+                                            is_modified(modified, pos+cols*screenoffset)),
+                None => None,
+            };
+            class_color_cond(true, classcolor);
+            highlight_cond(true, highlighted);
             color_ascii_cond(true, pos+cols*screenoffset == cursorpos, cstate);
             if pos < buf.len() {
-                if let c @ 32...126 = buf[pos] {
-                    if c as char == '%' {
+                // Chars may start before the screen
+                if let Some(c) = settings.view.encoding.display(wholebuf, start + pos) {
+                    if c == '%' {
                         // '%' needs to be escaped by a '%' in ncurses
                         printw("%%");
                     } else {
-                        printw(&format!("{}", c as char));
+                        printw(&format!("{}", c));
                     }
                 } else {
                     // Mark non-ascii symbols
-                    printw(&format!("."));
+                    printw(".");
                 }
             } else if pos == buf.len() {
                 // Pad ascii with spaces
@@ -86,41 +120,76 @@ pub fn draw(
             }
 
             color_ascii_cond(false, pos+cols*screenoffset == cursorpos, cstate);
+            highlight_cond(false, highlighted);
+            class_color_cond(false, classcolor);
         }
-        printw("\n");
     }
-    for _ in 1 .. screenheight - rows {
-        // Put the cursor on last line of terminal
-        printw("\n");
-    }
-    printw(&format!("{}", command));
-    printw(&format!("{}", debug));
 }
 
-fn get_absolute_line(cols: usize, screenoffset: usize, z: usize) -> usize {
-    return z * cols + screenoffset * cols;
+// A cell of the view in the hex pane, pos is relative to the screen start at offset.
+// The whole cell is highlighted or marked modified when its first byte is.
+#[allow(clippy::too_many_arguments)]
+fn draw_cell(
+    buf: &[u8],
+    pos: usize,
+    cursorpos: usize,
+    cstate: Cursorstate,
+    offset: usize,
+    highlight: Option<(usize, usize)>,
+    settings: &Settings,
+    modified: &[Hunk],
+) {
+    let view = &settings.view;
+    let is_cursor = pos + offset <= cursorpos && cursorpos < pos + offset + view.group;
+    if pos >= buf.len() {
+        for _ in 0..view.width() {
+            color_ascii_cond(true, is_cursor && pos == buf.len(), cstate);
+            printw("-");
+            color_ascii_cond(false, is_cursor && pos == buf.len(), cstate);
+        }
+        printw(" ");
+        return;
+    }
+    let highlighted = is_highlighted(highlight, pos + offset);
+    let classcolor = if view.group == 1 || is_modified(modified, pos + offset) {
+        get_class_color(settings, buf[pos], is_cursor, is_modified(modified, pos + offset))
+    } else {
+        None
+    };
+    class_color_cond(true, classcolor);
+    highlight_cond(true, highlighted);
+    for (i, digit) in view.format(view.read(buf, pos)).chars().enumerate() {
+        let attr = match cstate {
+            Cursorstate::Digit(d) if is_cursor && d == i => Some(COLOR_PAIR(1) | A_STANDOUT()),
+            Cursorstate::Asciichar if is_cursor => Some(A_UNDERLINE()),
+            _ => None,
+        };
+        class_color_cond(true, attr);
+        printw(&format!("{}", digit));
+        class_color_cond(false, attr);
+    }
+    highlight_cond(false, highlighted);
+    class_color_cond(false, classcolor);
+    printw(" ");
 }
-pub fn get_screen_size(
-    cols: usize,
-    ) -> usize {
-    let screenheight: usize;
-    screenheight = getmaxy(stdscr()) as usize;
 
-    let mut ret: usize = 0;
+// Put the command or info text on last line of terminal (Like in vim)
+pub fn draw_statusline(text: &str) {
+    mv(getmaxy(stdscr()) - 1, 0);
+    addstr(text);
+}
 
-    // Last line reserved for Status/Commands/etc (Like in vim)
-    if screenheight >= 1 {
-        ret = (screenheight-1) * cols;
-    }
-    return ret;
+fn get_absolute_line(cols: usize, screenoffset: usize, z: usize) -> usize {
+    z * cols + screenoffset * cols
 }
-pub fn get_absolute_draw_indices(
+fn get_absolute_draw_indices(
     buflen: usize,
     cols: usize,
     screenoffset: usize,
+    rows: usize,
     ) -> (usize, usize) {
 
-    let max_draw_len:usize = cmp::min(buflen, get_screen_size(cols)); //hier muss bei get_screen_size noch auf 16er abgerundet werden?
+    let max_draw_len:usize = cmp::min(buflen, rows * cols);
 
     let starting_pos: usize = screenoffset * cols;
     let mut ending_pos: usize = starting_pos + max_draw_len;
@@ -128,7 +197,44 @@ pub fn get_absolute_draw_indices(
         ending_pos = buflen;
     }
 
-    return (starting_pos, ending_pos);
+    (starting_pos, ending_pos)
+}
+
+// The highlighted range includes the start and excludes the end
+fn is_highlighted(highlight: Option<(usize, usize)>, pos: usize) -> bool {
+    match highlight {
+        Some((start, end)) => start <= pos && pos < end,
+        None => false,
+    }
+}
+fn highlight_cond(color: bool, condition: bool) {
+    if condition {
+        if color {
+            attron(A_REVERSE());
+        } else {
+            attroff(A_REVERSE());
+        }
+    }
+}
+
+// The cursor keeps its own color, modified bytes are colored instead of their class
+fn get_class_color(settings: &Settings, c: u8, is_cursor: bool, modified: bool) -> Option<attr_t> {
+    if is_cursor {
+        None
+    } else if modified {
+        Some(settings.theme.modified_attr())
+    } else {
+        settings.theme.attr(c)
+    }
+}
+fn class_color_cond(color: bool, attr: Option<attr_t>) {
+    if let Some(attr) = attr {
+        if color {
+            attron(attr);
+        } else {
+            attroff(attr);
+        }
+    }
 }
 
 fn color_left_nibble(color: bool, cstate: Cursorstate) {
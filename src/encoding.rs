@@ -0,0 +1,236 @@
+extern crate libc;
+
+// Longest encoded char, a utf-8 sequence or an utf-16 surrogate pair
+pub const MAXCHARLEN: usize = 4;
+
+// Character encoding of the text pane, changed with :set enc=
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Ascii,
+    Latin1,
+    Cp437,
+    // EBCDIC
+    Cp037,
+    Utf8,
+    Utf16le,
+}
+
+// What the text pane shows for a byte
+#[derive(PartialEq, Debug)]
+pub enum Glyph {
+    // First byte of a char
+    Char(char),
+    // Following byte of a multi-byte char
+    Continuation,
+    // Byte which starts no valid char
+    Invalid,
+}
+
+// Upper half of code page 437, the lower half is ascii
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// Code page 037 to latin1, both cover the same 256 chars
+const CP037: [u8; 256] = [
+    0x00, 0x01, 0x02, 0x03, 0x9C, 0x09, 0x86, 0x7F, 0x97, 0x8D, 0x8E, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    0x10, 0x11, 0x12, 0x13, 0x9D, 0x85, 0x08, 0x87, 0x18, 0x19, 0x92, 0x8F, 0x1C, 0x1D, 0x1E, 0x1F,
+    0x80, 0x81, 0x82, 0x83, 0x84, 0x0A, 0x17, 0x1B, 0x88, 0x89, 0x8A, 0x8B, 0x8C, 0x05, 0x06, 0x07,
+    0x90, 0x91, 0x16, 0x93, 0x94, 0x95, 0x96, 0x04, 0x98, 0x99, 0x9A, 0x9B, 0x14, 0x15, 0x9E, 0x1A,
+    0x20, 0xA0, 0xE2, 0xE4, 0xE0, 0xE1, 0xE3, 0xE5, 0xE7, 0xF1, 0xA2, 0x2E, 0x3C, 0x28, 0x2B, 0x7C,
+    0x26, 0xE9, 0xEA, 0xEB, 0xE8, 0xED, 0xEE, 0xEF, 0xEC, 0xDF, 0x21, 0x24, 0x2A, 0x29, 0x3B, 0xAC,
+    0x2D, 0x2F, 0xC2, 0xC4, 0xC0, 0xC1, 0xC3, 0xC5, 0xC7, 0xD1, 0xA6, 0x2C, 0x25, 0x5F, 0x3E, 0x3F,
+    0xF8, 0xC9, 0xCA, 0xCB, 0xC8, 0xCD, 0xCE, 0xCF, 0xCC, 0x60, 0x3A, 0x23, 0x40, 0x27, 0x3D, 0x22,
+    0xD8, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0xAB, 0xBB, 0xF0, 0xFD, 0xFE, 0xB1,
+    0xB0, 0x6A, 0x6B, 0x6C, 0x6D, 0x6E, 0x6F, 0x70, 0x71, 0x72, 0xAA, 0xBA, 0xE6, 0xB8, 0xC6, 0xA4,
+    0xB5, 0x7E, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0xA1, 0xBF, 0xD0, 0xDD, 0xDE, 0xAE,
+    0x5E, 0xA3, 0xA5, 0xB7, 0xA9, 0xA7, 0xB6, 0xBC, 0xBD, 0xBE, 0x5B, 0x5D, 0xAF, 0xA8, 0xB4, 0xD7,
+    0x7B, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0xAD, 0xF4, 0xF6, 0xF2, 0xF3, 0xF5,
+    0x7D, 0x4A, 0x4B, 0x4C, 0x4D, 0x4E, 0x4F, 0x50, 0x51, 0x52, 0xB9, 0xFB, 0xFC, 0xF9, 0xFA, 0xFF,
+    0x5C, 0xF7, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0xB2, 0xD4, 0xD6, 0xD2, 0xD3, 0xD5,
+    0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0xB3, 0xDB, 0xDC, 0xD9, 0xDA, 0x9F,
+];
+
+extern "C" {
+    fn wcwidth(c: libc::wchar_t) -> libc::c_int;
+}
+
+// Uses the locale of the environment, chars beyond ascii are drawn in its charset
+pub fn set_locale() {
+    unsafe {
+        libc::setlocale(libc::LC_ALL, b"\0".as_ptr() as *const libc::c_char);
+    }
+}
+
+// Chars which take one column on the terminal, others would shift the line
+fn is_drawable(c: char) -> bool {
+    !c.is_control() && unsafe { wcwidth(c as libc::wchar_t) } == 1
+}
+
+// Length of the utf-8 sequence starting with the byte
+fn utf8_len(c: u8) -> usize {
+    match c {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 0,
+    }
+}
+
+fn utf16_unit(buf: &[u8], pos: usize) -> Option<u16> {
+    match (buf.get(pos), buf.get(pos + 1)) {
+        (Some(&low), Some(&high)) => Some((high as u16) << 8 | low as u16),
+        _ => None,
+    }
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "ascii" => Some(Encoding::Ascii),
+            "latin1" => Some(Encoding::Latin1),
+            "cp437" => Some(Encoding::Cp437),
+            "cp037" => Some(Encoding::Cp037),
+            "utf-8" => Some(Encoding::Utf8),
+            "utf-16le" => Some(Encoding::Utf16le),
+            _ => None,
+        }
+    }
+    // Decodes the char at pos, utf-16 chars start at even positions
+    pub fn decode(self, buf: &[u8], pos: usize) -> Glyph {
+        let c = buf[pos];
+        match self {
+            Encoding::Ascii if c < 0x80 => Glyph::Char(c as char),
+            Encoding::Ascii => Glyph::Invalid,
+            Encoding::Latin1 => Glyph::Char(c as char),
+            Encoding::Cp437 if c < 0x80 => Glyph::Char(c as char),
+            Encoding::Cp437 => Glyph::Char(CP437[c as usize - 0x80]),
+            Encoding::Cp037 => Glyph::Char(CP037[c as usize] as char),
+            Encoding::Utf8 => {
+                let len = utf8_len(c);
+                if len == 0 {
+                    // A following byte, part of a char when a sequence before reaches it
+                    let start = (1..MAXCHARLEN).filter(|&back| back <= pos).find(|&back| {
+                        let len = utf8_len(buf[pos - back]);
+                        len > back && Encoding::Utf8.decode(buf, pos - back) != Glyph::Invalid
+                    });
+                    return match start {
+                        Some(_) => Glyph::Continuation,
+                        None => Glyph::Invalid,
+                    };
+                }
+                match buf.get(pos..pos + len).map(std::str::from_utf8) {
+                    Some(Ok(s)) => Glyph::Char(s.chars().next().unwrap()),
+                    _ => Glyph::Invalid,
+                }
+            }
+            Encoding::Utf16le => {
+                if pos % 2 == 1 {
+                    return match utf16_unit(buf, pos - 1) {
+                        Some(_) => Glyph::Continuation,
+                        None => Glyph::Invalid,
+                    };
+                }
+                let unit = match utf16_unit(buf, pos) {
+                    Some(unit) => unit,
+                    None => return Glyph::Invalid,
+                };
+                // The low surrogate of a pair continues the high surrogate before
+                if (0xDC00..0xE000).contains(&unit) && pos >= 2
+                    && utf16_unit(buf, pos - 2).is_some_and(|high| (0xD800..0xDC00).contains(&high))
+                {
+                    return Glyph::Continuation;
+                }
+                let mut units = vec![unit];
+                if let Some(low) = utf16_unit(buf, pos + 2) {
+                    units.push(low);
+                }
+                match std::char::decode_utf16(units).next() {
+                    Some(Ok(c)) => Glyph::Char(c),
+                    _ => Glyph::Invalid,
+                }
+            }
+        }
+    }
+    // The char shown in the text pane, None for bytes shown as a dot
+    pub fn display(self, buf: &[u8], pos: usize) -> Option<char> {
+        match self.decode(buf, pos) {
+            Glyph::Char(c) if is_drawable(c) => Some(c),
+            Glyph::Continuation => Some(' '),
+            _ => None,
+        }
+    }
+    // Bytes of a typed char, None when the encoding has no such char
+    pub fn encode(self, c: char) -> Option<Vec<u8>> {
+        let code = c as u32;
+        match self {
+            Encoding::Ascii if code < 0x80 => Some(vec![code as u8]),
+            Encoding::Latin1 if code < 0x100 => Some(vec![code as u8]),
+            Encoding::Cp437 if code < 0x80 => Some(vec![code as u8]),
+            Encoding::Cp437 => CP437.iter().position(|&x| x == c).map(|i| vec![0x80 + i as u8]),
+            Encoding::Cp037 if code < 0x100 => CP037.iter().position(|&x| x as u32 == code).map(|i| vec![i as u8]),
+            Encoding::Utf8 => Some(c.to_string().into_bytes()),
+            Encoding::Utf16le => Some(c.encode_utf16(&mut [0; 2]).iter().flat_map(|unit| unit.to_le_bytes()).collect()),
+            _ => None,
+        }
+    }
+    // Start of the char before pos, for deleting it with backspace
+    pub fn prev_char(self, buf: &[u8], pos: usize) -> usize {
+        let mut start = pos.saturating_sub(1);
+        while start > 0 && self.decode(buf, start) == Glyph::Continuation {
+            start -= 1;
+        }
+        start
+    }
+}
+
+#[test]
+fn codepages_roundtrip() {
+    assert_eq!(Encoding::Cp037.decode(&[0xC1], 0), Glyph::Char('A'));
+    assert_eq!(Encoding::Cp037.encode('A'), Some(vec![0xC1]));
+    assert_eq!(Encoding::Cp437.decode(&[0xDB], 0), Glyph::Char('█'));
+    assert_eq!(Encoding::Cp437.encode('é'), Some(vec![0x82]));
+    assert_eq!(Encoding::Ascii.encode('é'), None);
+    assert_eq!(Encoding::Latin1.encode('é'), Some(vec![0xE9]));
+    for c in 0..=255u8 {
+        if let Glyph::Char(decoded) = Encoding::Cp037.decode(&[c], 0) {
+            assert_eq!(Encoding::Cp037.encode(decoded), Some(vec![c]));
+        }
+    }
+}
+#[test]
+fn utf8_spans_cells() {
+    let buf = "aé€".as_bytes();
+    assert_eq!(Encoding::Utf8.decode(buf, 1), Glyph::Char('é'));
+    assert_eq!(Encoding::Utf8.decode(buf, 2), Glyph::Continuation);
+    assert_eq!(Encoding::Utf8.decode(buf, 5), Glyph::Continuation);
+    assert_eq!(Encoding::Utf8.decode(&[0xA9, 0xC3], 0), Glyph::Invalid);
+    assert_eq!(Encoding::Utf8.decode(&[0xA9, 0xC3], 1), Glyph::Invalid);
+    assert_eq!(Encoding::Utf8.prev_char(buf, 6), 3);
+}
+#[test]
+fn utf16_surrogate_pairs() {
+    let buf = Encoding::Utf16le.encode('😀').unwrap();
+    assert_eq!(buf, [0x3D, 0xD8, 0x00, 0xDE]);
+    assert_eq!(Encoding::Utf16le.decode(&buf, 0), Glyph::Char('😀'));
+    assert_eq!(Encoding::Utf16le.decode(&buf, 1), Glyph::Continuation);
+    assert_eq!(Encoding::Utf16le.decode(&buf, 2), Glyph::Continuation);
+    assert_eq!(Encoding::Utf16le.decode(&[0x41, 0x00, 0x42], 2), Glyph::Invalid);
+    assert_eq!(Encoding::Utf16le.prev_char(&buf, 4), 0);
+}
+
+#[test]
+fn utf16_low_surrogate_runs() {
+    let buf: Vec<u8> = [0x00, 0xDC].iter().cycle().take(800_000).cloned().collect();
+    assert_eq!(Encoding::Utf16le.decode(&buf, buf.len() - 2), Glyph::Invalid);
+    assert_eq!(Encoding::Utf16le.prev_char(&buf, buf.len()), buf.len() - 2);
+}
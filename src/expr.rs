@@ -0,0 +1,82 @@
+use pest::iterators::Pair;
+use Rule;
+
+// Evaluates an expression of the grammar, here is the value of "." and
+// buflen is used for "$".
+// Returns None on overflow or when dividing by zero.
+pub fn eval(pair: Pair<Rule>, here: usize, buflen: usize) -> Option<i64> {
+    match pair.as_rule() {
+        Rule::expr | Rule::term => {
+            let mut inner = pair.into_inner();
+            let mut value = eval(inner.next()?, here, buflen)?;
+            while let Some(op) = inner.next() {
+                let operand = eval(inner.next()?, here, buflen)?;
+                value = match op.as_rule() {
+                    Rule::add => value.checked_add(operand)?,
+                    Rule::subtract => value.checked_sub(operand)?,
+                    Rule::multiply => value.checked_mul(operand)?,
+                    _ => value.checked_div(operand)?,
+                };
+            }
+            Some(value)
+        }
+        Rule::number => {
+            let number = pair.as_str();
+            if number.starts_with("0x") || number.starts_with("0X") {
+                i64::from_str_radix(&number[2..], 16).ok()
+            } else {
+                number.parse().ok()
+            }
+        }
+        Rule::here => Some(here as i64),
+        Rule::last => Some(buflen as i64 - 1),
+        _ => None,
+    }
+}
+
+// Evaluates the first expression inside of a command
+pub fn eval_first(cmd: Pair<Rule>, here: usize, buflen: usize) -> Option<i64> {
+    cmd.into_inner()
+        .find(|inner| inner.as_rule() == Rule::expr)
+        .and_then(|expr| eval(expr, here, buflen))
+}
+
+// Evaluates all expressions inside of a command, None if one is invalid
+pub fn eval_all(cmd: Pair<Rule>, here: usize, buflen: usize) -> Option<Vec<i64>> {
+    cmd.into_inner()
+        .filter(|inner| inner.as_rule() == Rule::expr)
+        .map(|expr| eval(expr, here, buflen))
+        .collect()
+}
+
+#[cfg(test)]
+fn eval_str(expr: &str) -> Option<i64> {
+    use pest::Parser;
+    use IdentParser;
+    let pair = IdentParser::parse(Rule::expr, expr).unwrap().next().unwrap();
+    eval(pair, 0x10, 0x100)
+}
+
+#[test]
+fn eval_numbers() {
+    assert_eq!(eval_str("512"), Some(512));
+    assert_eq!(eval_str("0x1F40"), Some(0x1F40));
+    assert_eq!(eval_str("0Xff"), Some(0xFF));
+}
+#[test]
+fn eval_precedence() {
+    assert_eq!(eval_str("0x400+3*16"), Some(0x430));
+    assert_eq!(eval_str("(0x400 + 3) * 16"), Some(0x4030));
+    assert_eq!(eval_str("10-2-3"), Some(5));
+    assert_eq!(eval_str("100/10/2"), Some(5));
+}
+#[test]
+fn eval_cursor_and_end() {
+    assert_eq!(eval_str(".+1"), Some(0x11));
+    assert_eq!(eval_str("$-0xF"), Some(0xF0));
+}
+#[test]
+fn eval_errors() {
+    assert_eq!(eval_str("1/0"), None);
+    assert_eq!(eval_str("0xFFFFFFFFFFFFFFFFFF"), None);
+}
@@ -1,3 +1,5 @@
+use memmem::{Searcher, TwoWaySearcher};
+
 // To search for nibbles in a byte array.
 // Requires two nibbles per byte.
 // All values above 0x0F will be used as wildcard.
@@ -7,12 +9,12 @@ pub trait FindOptSubset {
     fn find_subset(&self, subset: &[u8]) -> Option<usize>;
 }
 
-impl FindOptSubset for Vec<u8> {
+impl FindOptSubset for [u8] {
     fn find_subset(&self, subset: &[u8]) -> Option<usize> {
         if subset.len() > 2 * self.len() {
             return None;
         }
-        if subset.len() % 2 != 0 {
+        if !subset.len().is_multiple_of(2) {
             // Must be dividable by two
             return None;
         }
@@ -20,16 +22,10 @@ impl FindOptSubset for Vec<u8> {
         for a in 0..self.len() - subset.len() / 2 + 1 {
             for b in 0..subset.len() / 2 {
                 // if b is not a, skip searching at that position in a
-                // Logic: both match, ...
-                // or first nibble matches when second nibble is wildcard,
-                // or second nibble matches when first nibble is wildcard.
-                // or two wildcards at that position.
-                if !(
-                    ((subset[2*b  ] == self[a + b]>>4) && (subset[2*b+1] == self[a + b]%16)) ||
-                    ((subset[2*b  ] == self[a + b]>>4) && (subset[2*b+1] >= 0x10)) ||
-                    ((subset[2*b+1] == self[a + b]%16) && (subset[2*b  ] >= 0x10)) ||
-                    ((subset[2*b+1] >= 0x10) && (subset[2*b  ] >= 0x10))
-                   ) {
+                // Logic: each nibble either matches or is a wildcard.
+                let high = subset[2*b  ] >= 0x10 || subset[2*b  ] == self[a + b]>>4;
+                let low  = subset[2*b+1] >= 0x10 || subset[2*b+1] == self[a + b]%16;
+                if !(high && low) {
                     break; // element does not match
                 }
                 // when all elements of b match in a, return position of a
@@ -42,45 +38,106 @@ impl FindOptSubset for Vec<u8> {
     }
 }
 
+// Converts hex digits to nibbles, x and X become the wildcard 0x10.
+pub fn hex_to_nibbles(hex: &str) -> Vec<u8> {
+    hex.chars()
+        .map(|c| match c.to_digit(16) {
+            Some(nibble) => nibble as u8,
+            None => 0x10,
+        })
+        .collect()
+}
+
+pub fn bytes_to_nibbles(bytes: &[u8]) -> Vec<u8> {
+    let mut nibbles = vec![];
+    for c in bytes {
+        nibbles.push(c >> 4);
+        nibbles.push(c & 0x0F);
+    }
+    nibbles
+}
+
+// Joins two nibbles to one byte, wildcards become zero.
+pub fn nibbles_to_bytes(nibbles: &[u8]) -> Vec<u8> {
+    nibbles
+        .chunks(2)
+        .map(|n| (n[0] & 0x0F) << 4 | n.get(1).map_or(0, |low| low & 0x0F))
+        .collect()
+}
+
+// Replaces the nibble subset inside buf[start..end] with the replacement.
+// Only the first occurrence is replaced, unless global is set.
+// Returns the positions of the replacements, each one already includes
+// the length change of the replacements before.
+pub fn substitute(
+    buf: &mut Vec<u8>,
+    subset: &[u8],
+    replacement: &[u8],
+    start: usize,
+    end: usize,
+    global: bool,
+) -> Vec<usize> {
+    let len = subset.len() / 2;
+    let mut end = end;
+    let mut from = start;
+    let mut positions = vec![];
+    if len == 0 {
+        return positions;
+    }
+    while let Some(pos) = buf[from..].find_subset(subset).map(|pos| pos + from) {
+        if pos + len > end {
+            break;
+        }
+        buf.splice(pos..pos + len, replacement.iter().cloned());
+        end = end + replacement.len() - len;
+        from = pos + replacement.len();
+        positions.push(pos);
+        if !global || from >= buf.len() {
+            break;
+        }
+    }
+    positions
+}
+
 #[test]
 fn find_subset_partial_at_start() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x01];
     assert_eq!(buf.find_subset(&sub), Some(0));
 }
 #[test]
 fn find_subset_partial_at_middle() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x03, 0x00, 0x04];
     assert_eq!(buf.find_subset(&sub), Some(2));
 }
 #[test]
 fn find_subset_partial_at_end() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x04, 0x00, 0x05];
     assert_eq!(buf.find_subset(&sub), Some(3));
 }
 #[test]
 fn find_subset_partial_after_end() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x05, 0x00, 0x06];
     assert_eq!(buf.find_subset(&sub), None);
 }
 #[test]
 fn find_subset_partial_before_start() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x00, 0x00, 0x01];
     assert_eq!(buf.find_subset(&sub), None);
 }
 #[test]
 fn find_subset_short() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x02];
     assert_eq!(buf.find_subset(&sub), Some(1));
 }
 #[test]
 fn find_subset_too_long() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![
         0x00,
         0x01,
@@ -99,55 +156,289 @@ fn find_subset_too_long() {
 }
 #[test]
 fn find_subset_full() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00, 0x05];
     assert_eq!(buf.find_subset(&sub), Some(0));
 }
 #[test]
 fn find_subset_swapped() {
-    let buf = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    let buf = [0x01, 0x02, 0x03, 0x04, 0x05];
     let sub = vec![0x00, 0x05, 0x00, 0x04, 0x00, 0x03, 0x00, 0x02, 0x00, 0x01];
     assert_eq!(buf.find_subset(&sub), None);
 }
 #[test]
 fn find_subset_higher_than_9() {
-    let buf = vec![0x0A, 0x0C, 0x0D, 0x0E, 0x0F];
+    let buf = [0x0A, 0x0C, 0x0D, 0x0E, 0x0F];
     let sub = vec![0x00, 0x0C, 0x00, 0x0D, 0x00, 0x0E];
     assert_eq!(buf.find_subset(&sub), Some(1));
 }
 #[test]
 fn find_subset_higher_than_f() {
-    let buf = vec![0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
+    let buf = [0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
     let sub = vec![0x03, 0x0C, 0x01, 0x0D, 0x0E, 0x0E];
     assert_eq!(buf.find_subset(&sub), Some(1));
 }
 #[test]
 fn find_with_single_wildcard_0x10() {
-    let buf = vec![0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
+    let buf = [0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
     let sub = vec![0x03, 0x0C, 0x01, 0x10, 0x0E, 0x0E];
     assert_eq!(buf.find_subset(&sub), Some(1));
 }
 #[test]
 fn find_with_wildcard_0xf0() {
-    let buf = vec![0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
+    let buf = [0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
     let sub = vec![0x03, 0x0C, 0xF0, 0xF0, 0x0E, 0x0E];
     assert_eq!(buf.find_subset(&sub), Some(1));
 }
 #[test]
+#[allow(non_snake_case)]
 fn find_with_wildcard_xX() {
-    let buf = vec![0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
-    let sub = vec![0x03, 0x0C, 'x' as u8, 'X' as u8, 0x0E, 0x0E];
+    let buf = [0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
+    let sub = vec![0x03, 0x0C, b'x', b'X', 0x0E, 0x0E];
     assert_eq!(buf.find_subset(&sub), Some(1));
 }
 #[test]
 fn find_with_wildcards() {
-    let buf = vec![0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
+    let buf = [0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
     let sub = vec![0x10, 0x10, 0x10, 0x10, 0x10, 0x10];
     assert_eq!(buf.find_subset(&sub), Some(0));
 }
 #[test]
 fn find_shifted() {
-    let buf = vec![0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
+    let buf = [0x0A, 0x3C, 0x1D, 0xEE, 0x0F];
     let sub = vec![0x0A, 0x03];
     assert_eq!(buf.find_subset(&sub), None);
 }
+
+// To search for text in a byte array.
+// The pattern holds a (value, mask) pair per byte, a byte matches when
+// all bits set in the mask are equal to the value.
+// Ascii letters differ from their other case only in bit 5, so clearing
+// that bit in the mask ignores the case.
+
+pub fn text_pattern(text: &str, wide: bool, ignore_case: bool) -> Vec<(u8, u8)> {
+    let mask = |c: u8, is_ascii: bool| {
+        if ignore_case && is_ascii && c.is_ascii_alphabetic() {
+            0xDF
+        } else {
+            0xFF
+        }
+    };
+    let mut pattern = vec![];
+    if wide {
+        // utf-16le, low byte first
+        for unit in text.encode_utf16() {
+            let low = unit as u8;
+            let high = (unit >> 8) as u8;
+            pattern.push((low, mask(low, high == 0)));
+            pattern.push((high, 0xFF));
+        }
+    } else {
+        for c in text.bytes() {
+            pattern.push((c, mask(c, true)));
+        }
+    }
+    pattern
+}
+
+pub trait FindMasked {
+    fn find_masked(&self, pattern: &[(u8, u8)]) -> Option<usize>;
+}
+
+impl FindMasked for [u8] {
+    fn find_masked(&self, pattern: &[(u8, u8)]) -> Option<usize> {
+        if pattern.len() > self.len() {
+            return None;
+        }
+        (0..self.len() - pattern.len() + 1).find(|&a| {
+            pattern
+                .iter()
+                .zip(&self[a..])
+                .all(|(&(value, mask), &c)| c & mask == value & mask)
+        })
+    }
+}
+
+// A search pattern, either text or hex digits with wildcards.
+pub enum Needle {
+    Bytes(Vec<u8>),
+    Masked(Vec<(u8, u8)>),
+    Nibbles(Vec<u8>),
+}
+
+impl Needle {
+    pub fn from_text(text: &str, wide: bool, ignore_case: bool) -> Needle {
+        if wide || ignore_case {
+            Needle::Masked(text_pattern(text, wide, ignore_case))
+        } else {
+            Needle::Bytes(text.as_bytes().to_vec())
+        }
+    }
+    pub fn from_hex(hex: &str) -> Needle {
+        Needle::Nibbles(hex_to_nibbles(hex))
+    }
+    // Number of bytes covered by a match
+    pub fn len(&self) -> usize {
+        match *self {
+            Needle::Bytes(ref bytes) => bytes.len(),
+            Needle::Masked(ref pattern) => pattern.len(),
+            Needle::Nibbles(ref nibbles) => nibbles.len() / 2,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn find_from(&self, buf: &[u8], from: usize) -> Option<usize> {
+        if self.is_empty() || from >= buf.len() {
+            return None;
+        }
+        let found = match *self {
+            Needle::Bytes(ref bytes) => TwoWaySearcher::new(bytes).search_in(&buf[from..]),
+            Needle::Masked(ref pattern) => buf[from..].find_masked(pattern),
+            Needle::Nibbles(ref nibbles) => buf[from..].find_subset(nibbles),
+        };
+        found.map(|pos| pos + from)
+    }
+    // Finds all matches, which do not overlap.
+    pub fn find_all(&self, buf: &[u8]) -> Vec<usize> {
+        let mut positions = vec![];
+        let mut from = 0;
+        while let Some(pos) = self.find_from(buf, from) {
+            positions.push(pos);
+            from = pos + self.len();
+        }
+        positions
+    }
+    // Finds the first match after pos, continues at the start of buf.
+    pub fn find_next(&self, buf: &[u8], pos: usize) -> Option<usize> {
+        self.find_from(buf, pos + 1).or_else(|| self.find_from(buf, 0))
+    }
+}
+
+#[test]
+fn text_pattern_plain() {
+    let pattern = text_pattern("aB", false, false);
+    assert_eq!(pattern, vec![(b'a', 0xFF), (b'B', 0xFF)]);
+}
+#[test]
+fn text_pattern_wide() {
+    let pattern = text_pattern("aB", true, false);
+    assert_eq!(pattern, vec![(b'a', 0xFF), (0x00, 0xFF), (b'B', 0xFF), (0x00, 0xFF)]);
+}
+#[test]
+fn text_pattern_ignore_case_only_letters() {
+    let pattern = text_pattern("a1", false, true);
+    assert_eq!(pattern, vec![(b'a', 0xDF), (b'1', 0xFF)]);
+}
+#[test]
+fn text_pattern_wide_ignore_case_non_ascii() {
+    // U+4161 has a letter as low byte, but is no ascii letter
+    let pattern = text_pattern("\u{4161}", true, true);
+    assert_eq!(pattern, vec![(0x61, 0xFF), (0x41, 0xFF)]);
+}
+#[test]
+fn find_masked_exact() {
+    let buf = b"xxHelloxx";
+    assert_eq!(buf.find_masked(&text_pattern("Hello", false, false)), Some(2));
+    assert_eq!(buf.find_masked(&text_pattern("hello", false, false)), None);
+}
+#[test]
+fn find_masked_ignore_case() {
+    let buf = b"xxHeLLoxx";
+    assert_eq!(buf.find_masked(&text_pattern("hello", false, true)), Some(2));
+}
+#[test]
+fn find_masked_ignore_case_no_false_match() {
+    // '@' differs from '`' only in bit 5, but is no letter
+    let buf = b"`";
+    assert_eq!(buf.find_masked(&text_pattern("@", false, true)), None);
+}
+#[test]
+fn find_masked_wide() {
+    let buf = [0x00, b'H', 0x00, b'i', 0x00, 0x00];
+    assert_eq!(buf.find_masked(&text_pattern("Hi", true, false)), Some(1));
+    assert_eq!(buf.find_masked(&text_pattern("hI", true, true)), Some(1));
+}
+#[test]
+fn find_masked_at_end() {
+    let buf = b"abc";
+    assert_eq!(buf.find_masked(&text_pattern("bc", false, false)), Some(1));
+    assert_eq!(buf.find_masked(&text_pattern("bcd", false, false)), None);
+}
+#[test]
+fn hex_to_nibbles_with_wildcard() {
+    assert_eq!(hex_to_nibbles("aFx0"), vec![0x0A, 0x0F, 0x10, 0x00]);
+}
+#[test]
+fn nibbles_to_bytes_roundtrip() {
+    let bytes = vec![0xDE, 0xAD, 0x00];
+    assert_eq!(nibbles_to_bytes(&bytes_to_nibbles(&bytes)), bytes);
+}
+#[test]
+fn substitute_first_only() {
+    let mut buf = vec![0xAA, 0x01, 0xAA, 0x02];
+    let sub = hex_to_nibbles("AA");
+    assert_eq!(substitute(&mut buf, &sub, &[0xBB], 0, 4, false), vec![0]);
+    assert_eq!(buf, vec![0xBB, 0x01, 0xAA, 0x02]);
+}
+#[test]
+fn substitute_global_wildcard() {
+    let mut buf = vec![0xA1, 0x01, 0xA2, 0x02];
+    let sub = hex_to_nibbles("Ax");
+    assert_eq!(substitute(&mut buf, &sub, &[0xBB], 0, 4, true), vec![0, 2]);
+    assert_eq!(buf, vec![0xBB, 0x01, 0xBB, 0x02]);
+}
+#[test]
+fn substitute_longer_inserts() {
+    let mut buf = vec![0xAA, 0x01, 0xAA];
+    let sub = hex_to_nibbles("AA");
+    assert_eq!(substitute(&mut buf, &sub, &[0xAA, 0xAA], 0, 3, true), vec![0, 3]);
+    assert_eq!(buf, vec![0xAA, 0xAA, 0x01, 0xAA, 0xAA]);
+}
+#[test]
+fn substitute_empty_deletes() {
+    let mut buf = vec![0xAA, 0x01, 0xAA];
+    let sub = hex_to_nibbles("AA");
+    assert_eq!(substitute(&mut buf, &sub, &[], 0, 3, true), vec![0, 1]);
+    assert_eq!(buf, vec![0x01]);
+}
+#[test]
+fn substitute_inside_range() {
+    let mut buf = vec![0xAA, 0xAA, 0xAA, 0xAA];
+    let sub = hex_to_nibbles("AA");
+    assert_eq!(substitute(&mut buf, &sub, &[0x00], 1, 3, true), vec![1, 2]);
+    assert_eq!(buf, vec![0xAA, 0x00, 0x00, 0xAA]);
+}
+#[test]
+fn needle_len() {
+    assert_eq!(Needle::from_text("abc", false, false).len(), 3);
+    assert_eq!(Needle::from_text("abc", true, false).len(), 6);
+    assert_eq!(Needle::from_hex("AxBB").len(), 2);
+}
+#[test]
+fn needle_find_from() {
+    let buf = b"abcabc";
+    assert_eq!(Needle::from_text("bc", false, false).find_from(buf, 2), Some(4));
+    assert_eq!(Needle::from_text("BC", false, true).find_from(buf, 2), Some(4));
+    assert_eq!(Needle::from_hex("62").find_from(buf, 2), Some(4));
+    assert_eq!(Needle::from_hex("62").find_from(buf, 6), None);
+}
+#[test]
+fn needle_find_next_wraps() {
+    let buf = b"abcabc";
+    let needle = Needle::from_text("a", false, false);
+    assert_eq!(needle.find_next(buf, 0), Some(3));
+    assert_eq!(needle.find_next(buf, 3), Some(0));
+}
+#[test]
+fn needle_empty_never_matches() {
+    let buf = b"abc";
+    assert_eq!(Needle::from_text("", false, false).find_next(buf, 0), None);
+}
+#[test]
+fn needle_find_all() {
+    let buf = b"aaaxaa";
+    assert_eq!(Needle::from_text("aa", false, false).find_all(buf), vec![0, 4]);
+    assert_eq!(Needle::from_hex("xx").find_all(buf).len(), 6);
+    assert_eq!(Needle::from_text("b", false, false).find_all(buf), vec![]);
+}
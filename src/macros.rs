@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+// Keys recorded with qa to qz and replayed with @a to @z.
+// Each key is kept with the rest of its escape sequence.
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<String>>,
+    // Register and keys of the running recording
+    recording: Option<(char, Vec<String>)>,
+    // Last replayed register, for @@
+    last: Option<char>,
+}
+
+impl Macros {
+    pub fn start(&mut self, name: char) {
+        self.recording = Some((name, vec![]));
+    }
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|&(name, _)| name)
+    }
+    // Remembers a typed key while recording
+    pub fn record(&mut self, key: &str) {
+        if let Some((_, ref mut keys)) = self.recording {
+            keys.push(key.to_string());
+        }
+    }
+    // Stores the recording, without the q which stopped it
+    pub fn stop(&mut self) {
+        if let Some((name, mut keys)) = self.recording.take() {
+            keys.pop();
+            self.registers.insert(name, keys);
+        }
+    }
+    // Returns the keys of a register count times, @ is the last replayed register
+    pub fn replay(&mut self, name: char, count: usize) -> Option<Vec<String>> {
+        let name = if name == '@' { self.last? } else { name };
+        self.last = Some(name);
+        let keys = self.registers.get(&name)?;
+        Some(keys.iter().cycle().take(keys.len() * count).cloned().collect())
+    }
+}
+
+#[test]
+fn recording_drops_stopping_q() {
+    let mut macros = Macros::default();
+    macros.record("x");
+    assert_eq!(macros.recording(), None);
+    macros.start('a');
+    for key in &["x", "\u{1b}[A", "q"] {
+        macros.record(key);
+    }
+    macros.stop();
+    assert_eq!(macros.recording(), None);
+    assert_eq!(macros.replay('a', 2).unwrap(), ["x", "\u{1b}[A", "x", "\u{1b}[A"]);
+}
+#[test]
+fn replay_last_register() {
+    let mut macros = Macros::default();
+    assert_eq!(macros.replay('@', 1), None);
+    macros.start('b');
+    macros.record("l");
+    macros.record("q");
+    macros.stop();
+    assert_eq!(macros.replay('c', 1), None);
+    assert_eq!(macros.replay('b', 1).unwrap(), ["l"]);
+    assert_eq!(macros.replay('@', 3).unwrap(), ["l", "l", "l"]);
+}
@@ -8,14 +8,48 @@ use std::io::prelude::*;
 use std::fs::OpenOptions;
 use std::io::SeekFrom;
 use std::path::Path;
-use std::error::Error;
 use std::env;
+use std::cmp;
+use std::collections::VecDeque;
+use std::process;
 
 mod draw;
-use draw::draw;
-use draw::get_absolute_draw_indices;
+use draw::{draw, draw_statusline};
+mod settings;
+use settings::Settings;
+mod theme;
+use theme::Theme;
+mod view;
+use view::Radix;
+mod window;
+use window::{draw_separator, Direction, Region, Window, Windows};
+mod buffer;
+use buffer::{Buffer, Buffers, Bytes};
+mod undo;
+mod encoding;
+use encoding::{set_locale, Encoding, MAXCHARLEN};
+mod modified;
+use modified::{next_hunk, prev_hunk};
+mod diff;
+use diff::{diff, hunk_at, map_position, report, side_hunks, DiffCache, DiffHunk};
+mod matchlist;
+use matchlist::{draw_matchlist, MatchList};
+mod marks;
+use marks::{clamp_pos, JumpList, Marks};
+mod expr;
+use expr::{eval_all, eval_first};
+mod motion;
+use motion::{last_line, line_end, word_backward, word_end, word_forward};
+use motion::{is_printable, next_different, next_run, prev_different, prev_run, MINPRINTABLE};
+mod macros;
+use macros::Macros;
+mod change;
+use change::{insert_back, insert_key, inserted_len, overwrite_bytes, overwrite_key, repeat_pattern, Change, BACKSPACE, MAXBUFLEN};
+mod shell;
+use shell::{filter, run_command};
 mod find;
-use find::FindOptSubset;
+use find::Needle;
+use find::{bytes_to_nibbles, hex_to_nibbles, nibbles_to_bytes, substitute};
 
 extern crate ncurses;
 use ncurses::*;
@@ -28,65 +62,256 @@ extern crate pest;
 extern crate pest_derive;
 
 use pest::Parser;
+use pest::iterators::Pair;
 
 #[derive(Parser)]
 #[grammar = "cmd.pest"]
 struct IdentParser;
 
 extern crate memmem;
-use memmem::{Searcher, TwoWaySearcher};
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Cursorstate {
     Leftnibble,
     Rightnibble,
     Asciichar,
+    // Digit of a cell, in views other than the plain hex view
+    Digit(usize),
+}
+
+// Keys are parsed by a different grammar in each mode
+#[derive(PartialEq, Copy, Clone)]
+enum Mode {
+    Normal,
+    Insert,
+    Replace,
+}
+
+// Typed chars beyond ascii arrive as utf-8 sequences of several keys
+fn read_char(first: i32) -> char {
+    let len = match first {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first as u8];
+    for _ in 1..len {
+        bytes.push(getch() as u8);
+    }
+    match std::str::from_utf8(&bytes) {
+        Ok(s) => s.chars().next().unwrap(),
+        Err(_) => std::char::from_u32(first as u32).unwrap(),
+    }
+}
+
+// Time to wait for the rest of an escape sequence, like vim's ttimeoutlen
+const ESCTIMEOUT: i32 = 100;
+
+// Arrow keys send escape sequences, returns the rest of a sequence after an escape
+fn read_escape_sequence() -> String {
+    let mut sequence = String::new();
+    timeout(ESCTIMEOUT);
+    let next = getch();
+    if next == '[' as i32 {
+        let last = getch();
+        match std::char::from_u32(last as u32) {
+            Some(c @ 'A'..='D') => {
+                sequence.push('[');
+                sequence.push(c);
+            }
+            _ => {
+                // Not an arrow key, the keys are read again
+                if last != ERR {
+                    ungetch(last);
+                }
+                ungetch(next);
+            }
+        }
+    } else if next != ERR {
+        ungetch(next);
+    }
+    timeout(-1);
+    sequence
+}
+
+// Ex commands run at startup, one per line, in the home directory
+const CONFIGFILE: &str = ".hexdinorc";
+
+// Keys replayed at once, a recursive macro stops after this
+const MAXREPLAY: usize = 100_000;
+
+// Returns the range from the start of the visual selection to the cursor.
+// The end is excluded, so the byte under the cursor is part of the range.
+fn get_selection(visualstart: Option<usize>, cursorpos: usize) -> Option<(usize, usize)> {
+    visualstart.map(|v| (cmp::min(v, cursorpos), cmp::max(v, cursorpos) + 1))
+}
+
+// Returns the count typed before a command
+fn get_count(cmd: &Pair<Rule>) -> Option<usize> {
+    cmd.clone()
+        .into_inner()
+        .find(|inner| inner.as_rule() == Rule::count)
+        .and_then(|count| count.as_str().parse().ok())
+}
+
+// Returns the hex pattern of a fill command
+fn get_pattern(cmd: &Pair<Rule>) -> Vec<u8> {
+    cmd.clone()
+        .into_inner()
+        .find(|inner| inner.as_rule() == Rule::fillpattern)
+        .map_or(vec![], |pattern| nibbles_to_bytes(&hex_to_nibbles(pattern.as_str())))
+}
+
+// Returns the file name of a command
+fn get_filename(cmd: &Pair<Rule>) -> String {
+    cmd.clone()
+        .into_inner()
+        .find(|inner| inner.as_rule() == Rule::filename)
+        .map_or(String::new(), |name| name.as_str().to_string())
+}
+
+// Builds the needle of a search or hexsearch command
+fn get_needle(search: Pair<Rule>) -> Option<Needle> {
+    let mut wide = false;
+    let mut ignore_case = false;
+    for inner in search.into_inner() {
+        match inner.as_rule() {
+            Rule::searchwide => wide = true,
+            Rule::searchnocase => ignore_case = true,
+            Rule::searchstr => return Some(Needle::from_text(inner.as_str(), wide, ignore_case)),
+            Rule::searchbytes => return Some(Needle::from_hex(inner.as_str())),
+            _ => (),
+        }
+    }
+    None
+}
+
+// Builds the needle of an unfinished search, as if it was finished now
+fn get_preview_needle(command: &str) -> Option<Needle> {
+    let finished = format!("{}\n", command);
+    IdentParser::parse(Rule::hexsearch, &finished)
+        .or_else(|_| IdentParser::parse(Rule::search, &finished))
+        .ok()
+        .and_then(|mut pairs| pairs.next())
+        .and_then(get_needle)
+}
+
+// The file compared with the current one in diff mode
+fn diff_partner(diffpair: Option<(usize, usize)>, current: usize) -> Option<usize> {
+    match diffpair {
+        Some((a, b)) if a == current => Some(b),
+        Some((a, b)) if b == current => Some(a),
+        _ => None,
+    }
+}
+
+// Prints the differences of two files and returns the exit status of cmp:
+// 0 for equal files, 1 for differences and 2 for trouble
+fn print_diff(paths: &[String], ranges: bool) -> i32 {
+    if paths.len() != 2 {
+        eprintln!("--diff needs two files");
+        return 2;
+    }
+    let mut files = vec![];
+    for path in paths {
+        match std::fs::read(path) {
+            Ok(file) => files.push(file),
+            Err(why) => {
+                eprintln!("Could not read {}: {}", path, why);
+                return 2;
+            }
+        }
+    }
+    let hunks = diff(&files[0], &files[1]);
+    for line in report(&files[0], &files[1], &hunks, ranges) {
+        println!("{}", line);
+    }
+    if hunks.is_empty() { 0 } else { 1 }
 }
 
 fn main() {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
-    let mut buf = vec![];
+    let mut buf: Bytes;
     let mut cursorpos: usize = 0;
     let mut cstate: Cursorstate = Cursorstate::Leftnibble;
     // 0 = display data from first line of file
     let mut screenoffset: usize = 0;
-    const SPALTEN: usize = 16;
     let mut command = String::new();
-    let mut debug = String::new();
-
-    // start ncursesw
-    initscr();
-    let screenheight = getmaxy(stdscr()) as usize;
-    // ctrl+z and fg works with this
-    cbreak();
-    noecho();
-    start_color();
-    init_pair(1, COLOR_GREEN, COLOR_BLACK);
+    let mut mode = Mode::Normal;
+    // Bytes of the cell or char overwritten in replace mode, with the cursor and the buffer length before
+    let mut overwritten: Vec<(usize, Cursorstate, Vec<u8>, usize)> = vec![];
+    // Keys typed in insert or replace mode, and how often they are repeated on escape
+    let mut typed = String::new();
+    // Bytes typed in this insert session, backspace only edits the nibbles and digits of these
+    let mut typedbytes = 0..0;
+    let mut changecount: usize = 1;
+    // Repeated with .
+    let mut lastchange: Option<(Change, usize)> = None;
+    // Bytes of the last y, put with p and P
+    let mut yanked: Vec<u8> = vec![];
+    let mut macros = Macros::default();
+    // Keys of a replayed macro, parsed before reading new keys
+    let mut pending: VecDeque<String> = VecDeque::new();
+    let mut replayed: usize = 0;
+    // Messages for the user, shown while no command is typed
+    let mut infotext = String::new();
+    // Start of the selection while in visual mode
+    let mut visualstart: Option<usize> = None;
+    // Cursor position before the current search was started
+    let mut searchstart: Option<usize> = None;
+    let mut searchmatch: Option<(usize, usize)> = None;
+    // Result of :findall, the list has the focus instead of the hex view
+    let mut matchlist: Option<MatchList> = None;
+    let mut listfocus = false;
+    // Length of the buffer the list was made for
+    let mut listbuflen: usize = 0;
+    let mut marks = Marks::default();
+    let mut jumplist = JumpList::default();
+    // Bytes per word for w, b and e
+    let mut wordsize: usize = 4;
+    let mut settings = Settings::default();
 
     let args: Vec<_> = env::args().collect();
     let program = args[0].clone();
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("v", "version", "print the version");
+    opts.optflag("d", "", "show the differences of two files side by side");
+    opts.optflag("", "diff", "print the differing bytes of two files, like cmp -l");
+    opts.optflag("", "ranges", "with --diff, print a line per difference");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
-            println!("{}", f.to_string());
+            println!("{}", f);
             println!("Usage: {} FILE [options]", program);
-            endwin();
             return;
         }
     };
     if matches.opt_present("v") {
         println!("Version: {}", VERSION);
-        endwin();
         return;
     }
     if matches.opt_present("h") {
         println!("Usage: {} FILE [options]", program);
-        endwin();
         return;
     }
+    // The report is for scripts, the editor is not started
+    if matches.opt_present("diff") {
+        process::exit(print_diff(&matches.free, matches.opt_present("ranges")));
+    }
+
+    // start ncursesw, the locale is needed for chars beyond ascii
+    set_locale();
+    initscr();
+    let screenheight = getmaxy(stdscr()) as usize;
+    // ctrl+z and fg works with this
+    cbreak();
+    noecho();
+    start_color();
+    use_default_colors();
+    init_pair(1, COLOR_GREEN, COLOR_BLACK);
+
 
     if !has_colors() {
         endwin();
@@ -94,11 +319,10 @@ fn main() {
         return;
     }
 
-    let patharg = match matches.free.is_empty() {
+    let mut patharg = match matches.free.is_empty() {
         true => String::new(),
         false => matches.free[0].clone(),
     };
-    let path = Path::new(&patharg);
 
     if patharg.is_empty() {
         endwin();
@@ -106,132 +330,636 @@ fn main() {
         return;
     }
 
-    let mut file = match OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&path) {
+    let buffer = match Buffer::open(&patharg) {
         Err(why) => {
-            println!("Could not open {}: {}", path.display(), why.to_string());
+            println!("{}", why);
             endwin();
             return;
         }
-        Ok(file) => file,
+        Ok(buffer) => buffer,
     };
-    file.read_to_end(&mut buf).ok().expect(
-        "File could not be read.",
-    );
+    buf = buffer.buf;
+    // Content of the file on disk, to show the modified bytes
+    let mut original = buffer.original;
+    // Modified bytes of the current buffer
+    let mut changecache = buffer.changes;
+    let mut history = buffer.history;
+    // The other open files, the current one is kept above
+    let mut buffers = Buffers::new();
+    // Buffers compared side by side with -d
+    let mut diffpair: Option<(usize, usize)> = None;
+    // Their differences, found again only after one of them changed
+    let mut diffcache = DiffCache::default();
+    if matches.opt_present("d") {
+        match matches.free.get(1).map(|path| Buffer::open_existing(path)) {
+            Some(Ok(other)) => diffpair = Some((buffers.current(), buffers.add(other))),
+            Some(Err(why)) => {
+                endwin();
+                println!("{}", why);
+                return;
+            }
+            None => {
+                endwin();
+                println!("Diff mode needs two files!\n");
+                return;
+            }
+        }
+    }
 
-    let draw_range = get_absolute_draw_indices(buf.len(), SPALTEN, screenoffset);
-    draw(&buf[draw_range.0 .. draw_range.1], cursorpos, SPALTEN, &command, &mut debug, cstate, screenoffset);
+    // Commands of the config file are run like typed, comments start with "
+    if let Some(home) = env::var_os("HOME") {
+        if let Ok(config) = std::fs::read_to_string(Path::new(&home).join(CONFIGFILE)) {
+            for line in config.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('"')) {
+                // Escape drops invalid commands
+                let keys = format!(":{}\n\u{1b}", line);
+                pending.extend(keys.chars().map(|c| c.to_string()));
+            }
+        }
+    }
+    settings.theme.init();
+
+    // The cursor and screenoffset above belong to the current window
+    let mut windows = Windows::new(Window { cursorpos, cstate, screenoffset, buffer: buffers.current() });
+    if let Some((_, other)) = diffpair {
+        // The new window on the left shows the first file, the right one the second
+        windows.split(Direction::Vertical, Window { cursorpos, cstate, screenoffset, buffer: buffers.current() });
+        windows.set(0, Window { cursorpos, cstate, screenoffset, buffer: other });
+    }
 
     let mut quitnow = false;
-    while quitnow == false {
-        let key = std::char::from_u32(getch() as u32).unwrap();
+    while !quitnow {
+        // Rows left for the windows, last line reserved for Status/Commands/etc
+        let listheight = matchlist.as_ref().map_or(0, |list| list.height());
+        let area = Region {
+            top: 0,
+            left: 0,
+            rows: cmp::max(screenheight.saturating_sub(1 + listheight), 1),
+            width: getmaxx(stdscr()) as usize,
+        };
+        let (regions, separators) = windows.regions(area);
+        let region = regions.iter().find(|&&(id, _)| id == windows.current()).unwrap().1;
+        let viewrows = cmp::max(region.rows, 1);
+        let cols = settings.view.bytes_per_row(region.width, buf.len());
+
+        // The cursor is on the start of a cell, on a nibble in the plain view or on a digit
+        cstate = settings.view.cursorstate(cstate);
+        if cstate != Cursorstate::Asciichar {
+            cursorpos -= cursorpos % settings.view.group;
+        }
+
+        // Always move screen when cursor leaves screen
+        if cursorpos >= (screenoffset + viewrows) * cols {
+            screenoffset = cursorpos / cols + 1 - viewrows;
+        }
+        if cursorpos < screenoffset * cols {
+            screenoffset = cursorpos / cols;
+        }
+
+        // Only draw when waiting for keys, not while replaying a macro
+        if pending.is_empty() {
+            // In diff mode the windows on the other file scroll along, the same bytes stay on the same row
+            let diffother = diff_partner(diffpair, buffers.current());
+            let differences = diffother.map(|other| diffcache.diff(&buf, &buffers.get(other).buf));
+            if let (Some(other), Some(differences)) = (diffother, differences) {
+                let otherlen = buffers.get(other).buf.len();
+                let row = cursorpos / cols - screenoffset;
+                for &(id, ref region) in &regions {
+                    let window = windows.get(id);
+                    if id != windows.current() && window.buffer == other {
+                        let othercols = settings.view.bytes_per_row(region.width, otherlen);
+                        let mut pos = clamp_pos(map_position(differences, cursorpos), otherlen);
+                        if cstate != Cursorstate::Asciichar {
+                            pos -= pos % settings.view.group;
+                        }
+                        let screenoffset = (pos / othercols).saturating_sub(row);
+                        windows.set(id, Window { cursorpos: pos, cstate, screenoffset, buffer: other });
+                    }
+                }
+            }
+
+            erase();
+            let highlight = searchmatch.or_else(|| get_selection(visualstart, cursorpos));
+            // Differences to the other file are shown instead of the changes
+            let hunks = match differences {
+                Some(differences) => side_hunks(differences),
+                None => side_hunks(changecache.diff(&buf, &original)),
+            };
+            for &(id, ref region) in &regions {
+                if id == windows.current() {
+                    draw(&buf, region, cursorpos, cols, cstate, screenoffset, highlight, &marks, &settings, &hunks);
+                } else {
+                    let window = windows.get(id);
+                    let otherhunks;
+                    let (buf, marks, hunks) = if window.buffer == buffers.current() {
+                        (&buf, &marks, &hunks)
+                    } else {
+                        let buffer = buffers.get_mut(window.buffer);
+                        otherhunks = match differences {
+                            Some(differences) if Some(window.buffer) == diffother => {
+                                let swapped: Vec<DiffHunk> = differences.iter().map(|hunk| hunk.swap()).collect();
+                                side_hunks(&swapped)
+                            }
+                            _ => side_hunks(buffer.changes.diff(&buffer.buf, &buffer.original)),
+                        };
+                        (&buffer.buf, &buffer.marks, &otherhunks)
+                    };
+                    // The buffer may have shrunk since the window was current
+                    let cols = settings.view.bytes_per_row(region.width, buf.len());
+                    let cursorpos = clamp_pos(window.cursorpos, buf.len());
+                    let screenoffset = cmp::min(window.screenoffset, cursorpos / cols);
+                    draw(buf, region, cursorpos, cols, window.cstate, screenoffset, None, marks, &settings, hunks);
+                }
+            }
+            for separator in &separators {
+                draw_separator(separator);
+            }
+            if let Some(ref list) = matchlist {
+                draw_matchlist(list, &buf, area.rows, listfocus, &settings.view);
+            }
+            let mut modetext = match mode {
+                Mode::Normal => "",
+                Mode::Insert => "-- INSERT --",
+                Mode::Replace => "-- REPLACE --",
+            }.to_string();
+            if let Some(name) = macros.recording() {
+                if !modetext.is_empty() {
+                    modetext.push(' ');
+                }
+                modetext.push_str(&format!("recording @{}", name));
+            }
+            draw_statusline(if !command.is_empty() {
+                &command
+            } else if !infotext.is_empty() {
+                &infotext
+            } else {
+                &modetext
+            });
+        }
+
+        let key = match pending.pop_front() {
+            Some(key) => {
+                replayed += 1;
+                key
+            }
+            None => {
+                replayed = 0;
+                let mut key = read_char(getch()).to_string();
+                if key == "\u{1b}" {
+                    key.push_str(&read_escape_sequence());
+                }
+                macros.record(&key);
+                key
+            }
+        };
         printw(&format!("   {:?}   ", key));
-        command.push_str(&key.clone().to_string());
+        command.push_str(&key);
+        infotext.clear();
 
         let parsethisstring = command.clone();
-        let commands = IdentParser::parse(Rule::cmd_list, &parsethisstring)
+        let entry = match mode {
+            Mode::Normal => Rule::cmd_list,
+            Mode::Insert => Rule::insert_list,
+            Mode::Replace => Rule::overwrite_list,
+        };
+        let commands = IdentParser::parse(entry, &parsethisstring)
             .unwrap_or_else(|e| panic!("{}", e));
 
         let mut clear = true;
         let mut save = false;
+        // State of the window which becomes current
+        let mut switchto: Option<Window> = None;
+        let mut switchbuffer: Option<usize> = None;
         for cmd in commands {
             match cmd.as_rule() {
+                Rule::down | Rule::up | Rule::top | Rule::bottom | Rule::enter if listfocus => {
+                    if let Some(ref mut list) = matchlist {
+                        match cmd.as_rule() {
+                            Rule::down => list.select_next(),
+                            Rule::up => list.select_prev(),
+                            Rule::top => list.select(0),
+                            Rule::bottom => list.select(usize::MAX),
+                            _ => {
+                                // Jump to the match and return to the hex view
+                                jumplist.push(cursorpos);
+                                cursorpos = clamp_pos(list.current(), buf.len());
+                                listfocus = false;
+                            }
+                        }
+                    }
+                }
                 Rule::down => {
                     printw(&format!("{:?}", cmd.as_rule()));
-                    if cursorpos + SPALTEN < buf.len() {
+                    if cursorpos + cols < buf.len() {
                         // not at end
-                        cursorpos += SPALTEN;
+                        cursorpos += cols;
                     } else {
                         // when at end
-                        if buf.len() != 0 {
+                        if !buf.is_empty() {
                             // Suppress underflow
                             cursorpos = buf.len() - 1;
                         }
                     }
                 }
-                Rule::up => {
-                    if cursorpos >= SPALTEN {
-                        cursorpos -= SPALTEN;
-                    }
+                Rule::up if cursorpos >= cols => {
+                    cursorpos -= cols;
                 }
                 Rule::left => {
-                    if cstate == Cursorstate::Asciichar {
-                        if cursorpos > 0 {
-                            cursorpos -= 1;
+                    if let Cursorstate::Digit(digit) = cstate {
+                        if digit > 0 {
+                            cstate = Cursorstate::Digit(digit - 1);
+                        } else if cursorpos >= settings.view.group {
+                            cstate = Cursorstate::Digit(settings.view.width() - 1);
+                            cursorpos -= settings.view.group;
                         }
+                    } else if cstate == Cursorstate::Asciichar {
+                        cursorpos = cursorpos.saturating_sub(1);
                     } else if cstate == Cursorstate::Rightnibble {
                         cstate = Cursorstate::Leftnibble;
-                    } else if cstate == Cursorstate::Leftnibble {
-                        if cursorpos > 0 {
-                            // not at start
-                            cstate = Cursorstate::Rightnibble;
-                            cursorpos -= 1;
-                        }
+                    } else if cstate == Cursorstate::Leftnibble && cursorpos > 0 {
+                        // not at start
+                        cstate = Cursorstate::Rightnibble;
+                        cursorpos -= 1;
                     }
                 }
                 Rule::right => {
-                    if cstate == Cursorstate::Asciichar {
+                    if let Cursorstate::Digit(digit) = cstate {
+                        if digit + 1 < settings.view.width() {
+                            cstate = Cursorstate::Digit(digit + 1);
+                        } else if cursorpos + settings.view.group < buf.len() {
+                            cstate = Cursorstate::Digit(0);
+                            cursorpos += settings.view.group;
+                        }
+                    } else if cstate == Cursorstate::Asciichar {
                         if cursorpos + 1 < buf.len() {
                             // not at end
                             cursorpos += 1;
                         }
                     } else if cstate == Cursorstate::Leftnibble {
                         cstate = Cursorstate::Rightnibble;
-                    } else if cstate == Cursorstate::Rightnibble {
-                        if cursorpos + 1 < buf.len() {
-                            // not at end
-                            cstate = Cursorstate::Leftnibble;
-                            cursorpos += 1;
-                        }
+                    } else if cstate == Cursorstate::Rightnibble && cursorpos + 1 < buf.len() {
+                        // not at end
+                        cstate = Cursorstate::Leftnibble;
+                        cursorpos += 1;
                     }
                 }
                 Rule::start => {
-                    cursorpos -= cursorpos % SPALTEN; // jump to start of line
+                    cursorpos -= cursorpos % cols; // jump to start of line
                     if cstate == Cursorstate::Rightnibble {
                         cstate = Cursorstate::Leftnibble;
                     }
                 }
                 Rule::end => {
-                    // check if no overflow
-                    if cursorpos - (cursorpos % SPALTEN) + (SPALTEN - 1) < buf.len() {
-                        // jump to end of line
-                        cursorpos = cursorpos - (cursorpos % SPALTEN) + (SPALTEN - 1);
-                    } else {
-                        // jump to end of line
-                        cursorpos = buf.len() - 1
-                    }
+                    // jump to end of line
+                    cursorpos = line_end(cursorpos, cols, buf.len());
                     if cstate == Cursorstate::Leftnibble {
                         cstate = Cursorstate::Rightnibble;
                     }
                 }
                 Rule::top => {
+                    jumplist.push(cursorpos);
                     cursorpos = 0;
                 }
                 Rule::bottom => {
-                    cursorpos = buf.len() - 1;
-                    cursorpos -= cursorpos % SPALTEN; // jump to start of line
+                    jumplist.push(cursorpos);
+                    cursorpos = last_line(buf.len(), cols); // jump to start of line
+                }
+                Rule::wordforward | Rule::wordbackward | Rule::wordend => {
+                    cursorpos = match cmd.as_rule() {
+                        Rule::wordforward => word_forward(cursorpos, wordsize, buf.len()),
+                        Rule::wordbackward => word_backward(cursorpos, wordsize),
+                        _ => word_end(cursorpos, wordsize, buf.len()),
+                    };
+                    if cstate != Cursorstate::Asciichar {
+                        cstate = if cmd.as_rule() == Rule::wordend {
+                            Cursorstate::Rightnibble
+                        } else {
+                            Cursorstate::Leftnibble
+                        };
+                    }
+                }
+                Rule::nextdiff | Rule::prevdiff | Rule::nextnonzero | Rule::prevnonzero |
+                Rule::nextnonff | Rule::prevnonff | Rule::nextprintable | Rule::prevprintable => {
+                    let found = match cmd.as_rule() {
+                        Rule::nextdiff => next_different(&buf, cursorpos),
+                        Rule::prevdiff => prev_different(&buf, cursorpos),
+                        Rule::nextnonzero => next_run(&buf, cursorpos, 1, |c| c != 0x00),
+                        Rule::prevnonzero => prev_run(&buf, cursorpos, 1, |c| c != 0x00),
+                        Rule::nextnonff => next_run(&buf, cursorpos, 1, |c| c != 0xFF),
+                        Rule::prevnonff => prev_run(&buf, cursorpos, 1, |c| c != 0xFF),
+                        Rule::nextprintable => next_run(&buf, cursorpos, MINPRINTABLE, is_printable),
+                        _ => prev_run(&buf, cursorpos, MINPRINTABLE, is_printable),
+                    };
+                    if let Some(pos) = found {
+                        cursorpos = pos;
+                        if cstate == Cursorstate::Rightnibble {
+                            cstate = Cursorstate::Leftnibble;
+                        }
+                    }
+                }
+                Rule::nextchange | Rule::prevchange => {
+                    let hunks = match diff_partner(diffpair, buffers.current()) {
+                        Some(other) => side_hunks(diffcache.diff(&buf, &buffers.get(other).buf)),
+                        None => side_hunks(changecache.diff(&buf, &original)),
+                    };
+                    let found = if cmd.as_rule() == Rule::nextchange {
+                        next_hunk(&hunks, cursorpos)
+                    } else {
+                        prev_hunk(&hunks, cursorpos)
+                    };
+                    match found {
+                        Some(pos) => {
+                            cursorpos = clamp_pos(pos, buf.len());
+                            if cstate == Cursorstate::Rightnibble {
+                                cstate = Cursorstate::Leftnibble;
+                            }
+                        }
+                        None => infotext.push_str("No more changes"),
+                    }
+                }
+                Rule::pagedown | Rule::pageup | Rule::halfpagedown | Rule::halfpageup => {
+                    // Scroll the screen and move the cursor along
+                    let lines = match cmd.as_rule() {
+                        Rule::pagedown | Rule::pageup => viewrows,
+                        _ => cmp::max(viewrows / 2, 1),
+                    };
+                    let lastline = buf.len().saturating_sub(1) / cols;
+                    if cmd.as_rule() == Rule::pagedown || cmd.as_rule() == Rule::halfpagedown {
+                        screenoffset = cmp::min(screenoffset + lines, lastline);
+                        cursorpos = clamp_pos(cursorpos + lines * cols, buf.len());
+                    } else {
+                        screenoffset = screenoffset.saturating_sub(lines);
+                        cursorpos = cursorpos.saturating_sub(lines * cols);
+                    }
+                }
+                Rule::screentop | Rule::screenmiddle | Rule::screenbottom => {
+                    let lastline = buf.len().saturating_sub(1) / cols;
+                    let bottomline = cmp::min(screenoffset + viewrows - 1, lastline);
+                    let line = match cmd.as_rule() {
+                        Rule::screentop => screenoffset,
+                        Rule::screenmiddle => (screenoffset + bottomline) / 2,
+                        _ => bottomline,
+                    };
+                    cursorpos = line * cols; // jump to start of line
+                }
+                Rule::recenter => {
+                    screenoffset = (cursorpos / cols).saturating_sub(viewrows / 2);
+                }
+                Rule::recentertop => {
+                    screenoffset = cursorpos / cols;
+                }
+                Rule::recenterbottom => {
+                    screenoffset = (cursorpos / cols + 1).saturating_sub(viewrows);
                 }
                 Rule::replace => {
                     // printw("next char will be the replacement!");
                     clear = false;
                 }
-                Rule::remove => {
-                    // check if in valid range
-                    if buf.len() > 0 && cursorpos < buf.len() {
-                        // remove the current char
-                        buf.remove(cursorpos);
+                Rule::replaceby | Rule::remove => {
+                    let change = match cmd.as_rule() {
+                        Rule::replaceby => Change::Replace(cmd.as_str().chars().last().unwrap()),
+                        _ => Change::Remove,
+                    };
+                    let count = get_count(&cmd).unwrap_or(1);
+                    change.apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, count);
+                    lastchange = Some((change, count));
+                }
+                Rule::yank => {
+                    let count = get_count(&cmd).unwrap_or(1);
+                    let (start, end) = get_selection(visualstart, cursorpos)
+                        .unwrap_or((cursorpos, cursorpos.saturating_add(count)));
+                    let start = cmp::min(start, buf.len());
+                    yanked = buf[start..cmp::min(end, buf.len())].to_vec();
+                    infotext.push_str(&format!("{} bytes yanked", yanked.len()));
+                    visualstart = None;
+                }
+                Rule::put | Rule::putbefore => {
+                    if yanked.is_empty() {
+                        infotext.push_str("Nothing yanked");
+                    } else {
+                        let change = Change::Put(yanked.clone(), cmd.as_rule() == Rule::put);
+                        let count = get_count(&cmd).unwrap_or(1);
+                        if change.fits(buf.len(), count) {
+                            change.apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, count);
+                            lastchange = Some((change, count));
+                        } else {
+                            infotext.push_str("Invalid count");
+                        }
                     }
-                    // always perform the movement if possible
-                    if cursorpos > 0 && cursorpos >= buf.len() {
-                        cursorpos -= 1;
+                }
+                Rule::insert | Rule::overwrite => {
+                    mode = if cmd.as_rule() == Rule::insert { Mode::Insert } else { Mode::Replace };
+                    changecount = get_count(&cmd).unwrap_or(1);
+                    typed.clear();
+                    typedbytes = 0..0;
+                    overwritten.clear();
+                }
+                Rule::insertment => {
+                    let c = cmd.as_str().chars().next().unwrap();
+                    insert_key(&mut buf, &mut marks, &mut cursorpos, &mut cstate, c, &settings.view, &mut typedbytes);
+                    typed.push(c);
+                }
+                Rule::insertback => {
+                    insert_back(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, &mut typedbytes);
+                    typed.push(BACKSPACE);
+                }
+                Rule::overwritement => {
+                    let c = cmd.as_str().chars().next().unwrap();
+                    let cellend = cmp::min(cursorpos + cmp::max(settings.view.group, MAXCHARLEN), buf.len());
+                    let cell = buf.get(cursorpos..cellend).unwrap_or(&[]).to_vec();
+                    let before = (cursorpos, cstate, cell, buf.len());
+                    if overwrite_key(&mut buf, &mut cursorpos, &mut cstate, c, &settings.view) {
+                        overwritten.push(before);
+                        typed.push(c);
                     }
                 }
-                Rule::insert => {
-                    printw("next chars will be inserted!");
-                    clear = false;
+                Rule::overwriteback => {
+                    // Restore the original bytes, only keys typed in this session are undone
+                    if let Some((pos, state, original, len)) = overwritten.pop() {
+                        // Remove the appended bytes
+                        buf.truncate(len);
+                        buf[pos..pos + original.len()].copy_from_slice(&original);
+                        cursorpos = pos;
+                        cstate = state;
+                        typed.pop();
+                    }
+                }
+                Rule::recordreg => macros.start(cmd.as_str().chars().next().unwrap()),
+                Rule::recordstop => {
+                    if macros.recording().is_some() {
+                        macros.stop();
+                    } else {
+                        // Wait for the register
+                        clear = false;
+                    }
+                }
+                Rule::replay => {
+                    let name = cmd.as_str().chars().last().unwrap();
+                    let count = get_count(&cmd).unwrap_or(1);
+                    match macros.replay(name, count) {
+                        Some(_) if replayed > MAXREPLAY => {
+                            infotext.push_str("Macro stopped, too many keys");
+                            pending.clear();
+                        }
+                        Some(keys) => {
+                            // Replay before the rest of a running macro
+                            for key in keys.into_iter().rev() {
+                                pending.push_front(key);
+                            }
+                        }
+                        None => infotext.push_str("Register is empty"),
+                    }
+                }
+                Rule::fill => {
+                    let range = match eval_all(cmd.clone(), cursorpos, buf.len()) {
+                        Some(ref v) if v.len() == 2 && v[0] >= 0 && v[1] >= 0 => {
+                            Some((v[0] as usize, v[1] as usize))
+                        }
+                        // Without a range, fill the visual selection
+                        Some(ref v) if v.is_empty() => {
+                            get_selection(visualstart, cursorpos).map(|(start, end)| (start, end - start))
+                        }
+                        _ => None,
+                    };
+                    match range {
+                        // The range is overwritten, the file does not grow
+                        Some((start, len)) if start.checked_add(len).is_some_and(|end| end <= buf.len()) => {
+                            let change = Change::Fill(repeat_pattern(&get_pattern(&cmd), len));
+                            cursorpos = start;
+                            change.apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, 1);
+                            lastchange = Some((change, 1));
+                            visualstart = None;
+                            infotext.push_str(&format!("{} bytes filled", len));
+                        }
+                        _ => infotext.push_str("Invalid range"),
+                    }
+                }
+                Rule::insertcopies => {
+                    let pattern = get_pattern(&cmd);
+                    let len = eval_first(cmd.clone(), cursorpos, buf.len())
+                        .filter(|&copies| copies >= 0)
+                        .and_then(|copies| inserted_len(pattern.len(), copies as usize, buf.len()));
+                    match len {
+                        Some(len) => {
+                            let change = Change::InsertBytes(repeat_pattern(&pattern, len));
+                            change.apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, 1);
+                            lastchange = Some((change, 1));
+                        }
+                        None => infotext.push_str("Invalid count"),
+                    }
+                }
+                Rule::truncate | Rule::resize | Rule::append => {
+                    let fillbyte = cmd.clone()
+                        .into_inner()
+                        .find(|inner| inner.as_rule() == Rule::fillbyte)
+                        .map_or(0, |byte| u8::from_str_radix(byte.as_str(), 16).unwrap());
+                    let newlen = eval_first(cmd.clone(), cursorpos, buf.len())
+                        .filter(|&n| n >= 0)
+                        .and_then(|n| match cmd.as_rule() {
+                            // Truncating never extends the file
+                            Rule::truncate => Some(cmp::min(n as usize, buf.len())),
+                            Rule::resize => Some(n as usize),
+                            _ => buf.len().checked_add(n as usize),
+                        });
+                    match newlen {
+                        // Growing stops at MAXBUFLEN, larger files can still shrink
+                        Some(newlen) if newlen <= buf.len() || newlen <= MAXBUFLEN => {
+                            if newlen < buf.len() {
+                                marks.removed(newlen, buf.len() - newlen);
+                            }
+                            buf.resize(newlen, fillbyte);
+                            cursorpos = clamp_pos(cursorpos, buf.len());
+                            infotext.push_str(&format!("File is now {} bytes", buf.len()));
+                        }
+                        _ => infotext.push_str("Invalid length"),
+                    }
+                }
+                Rule::readfile => {
+                    let name = get_filename(&cmd);
+                    match std::fs::read(&name) {
+                        Ok(data) => {
+                            let overwrite = cmd.clone().into_inner().any(|inner| inner.as_rule() == Rule::readoverwrite);
+                            if overwrite {
+                                overwrite_bytes(&mut buf, cursorpos, &data);
+                            } else {
+                                Change::InsertBytes(data.clone()).apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, 1);
+                            }
+                            infotext.push_str(&format!("{} bytes read from {}", data.len(), name));
+                        }
+                        Err(why) => infotext.push_str(&format!("Could not read {}: {}", name, why)),
+                    }
+                }
+                Rule::writefile => {
+                    let name = get_filename(&cmd);
+                    let range = match eval_all(cmd.clone(), cursorpos, buf.len()) {
+                        Some(ref v) if v.len() == 2 && v[0] >= 0 && v[1] >= 0 => {
+                            let start = cmp::min(v[0] as usize, buf.len());
+                            Some((start, cmp::min(start + v[1] as usize, buf.len())))
+                        }
+                        // Without a range, write the whole file
+                        Some(ref v) if v.is_empty() => Some((0, buf.len())),
+                        _ => None,
+                    };
+                    match range {
+                        Some((start, end)) => match std::fs::write(&name, &buf[start..end]) {
+                            Ok(()) => infotext.push_str(&format!("{} bytes written to {}", end - start, name)),
+                            Err(why) => infotext.push_str(&format!("Could not write {}: {}", name, why)),
+                        },
+                        None => infotext.push_str("Invalid range"),
+                    }
+                }
+                Rule::filter => {
+                    let mut range = None;
+                    for inner in cmd.clone().into_inner() {
+                        match inner.as_rule() {
+                            Rule::rangeall => range = Some((0, buf.len())),
+                            Rule::rangevisual => range = get_selection(visualstart, cursorpos),
+                            _ => (),
+                        }
+                    }
+                    if range.is_none() {
+                        // The end of start,end is included, like the lines in vim
+                        range = match eval_all(cmd.clone(), cursorpos, buf.len()) {
+                            Some(ref v) if v.len() == 2 && 0 <= v[0] && v[0] <= v[1] => {
+                                Some((v[0] as usize, v[1] as usize + 1))
+                            }
+                            _ => None,
+                        };
+                    }
+                    let shellcmd = cmd.clone().into_inner().find(|inner| inner.as_rule() == Rule::shellcmd).unwrap();
+                    match range {
+                        Some((start, end)) => {
+                            let start = cmp::min(start, buf.len());
+                            let end = cmp::min(end, buf.len());
+                            match filter(shellcmd.as_str(), buf[start..end].to_vec()) {
+                                Ok(output) => {
+                                    marks.replaced(start, end - start, output.len());
+                                    infotext.push_str(&format!("{} bytes replaced by {} bytes", end - start, output.len()));
+                                    buf.splice(start..end, output);
+                                    cursorpos = clamp_pos(start, buf.len());
+                                    visualstart = None;
+                                }
+                                Err(why) => infotext.push_str(&format!("Command failed: {}", why)),
+                            }
+                        }
+                        None => infotext.push_str("Invalid range"),
+                    }
+                }
+                Rule::shell => {
+                    let shellcmd = cmd.clone().into_inner().find(|inner| inner.as_rule() == Rule::shellcmd).unwrap();
+                    run_command(shellcmd.as_str(), &patharg);
+                }
+                Rule::repeat => {
+                    if let Some((change, recorded)) = lastchange.take() {
+                        // A new count replaces the recorded one
+                        let count = get_count(&cmd).unwrap_or(recorded);
+                        if change.fits(buf.len(), count) {
+                            change.apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, count);
+                            lastchange = Some((change, count));
+                        } else {
+                            infotext.push_str("Invalid count");
+                            lastchange = Some((change, recorded));
+                        }
+                    }
                 }
                 Rule::jumpascii => {
                     if cstate == Cursorstate::Asciichar {
@@ -240,114 +968,316 @@ fn main() {
                         cstate = Cursorstate::Asciichar;
                     }
                 }
+                Rule::visual => {
+                    visualstart = match visualstart {
+                        Some(_) => None,
+                        None => Some(cursorpos),
+                    };
+                }
                 Rule::helpfile => {
-                    command.push_str("No helpfile yet");
+                    infotext.push_str("No helpfile yet");
                 }
                 Rule::backspace => {
                     command.pop();
                     command.pop();
                     clear = false;
                 }
-                Rule::saveandexit => {
-                    save = true;
-                    quitnow = true;
-                }
-                Rule::exit => quitnow = true,
-                Rule::save => save = true,
-
-                _ => (),
-            }
-
-            for inner_cmd in cmd.into_inner() {
-                match inner_cmd.as_rule() {
-                    Rule::replacement => {
-                        // TODO: use inner_cmd and not just "key"
-                        // printw(&format!("Replacement: {:?}", inner_cmd.as_str()));
-                        if cstate == Cursorstate::Asciichar {
-                            if cursorpos >= buf.len() {
-                                buf.insert(cursorpos, 0);
-                            }
-                            // buf[cursorpos] = inner_cmd.as_str();
-                            buf[cursorpos] = key as u8;
+                // Like in vim, only the last window quits
+                Rule::saveandexit | Rule::exit => {
+                    if cmd.as_rule() == Rule::saveandexit {
+                        save = true;
+                    }
+                    switchto = windows.close();
+                    if switchto.is_none() {
+                        // Unsaved buffers need a ! to quit, :wq saves the current one
+                        let force = cmd.as_str().contains('!') || cmd.as_str() == "ZQ";
+                        let unsaved = if !save && buf != original {
+                            Some(buffers.current())
                         } else {
-                            let mask = if cstate == Cursorstate::Leftnibble {
-                                0x0F
-                            } else {
-                                0xF0
-                            };
-                            let shift = if cstate == Cursorstate::Leftnibble {
-                                4
+                            buffers.unsaved()
+                        };
+                        match unsaved {
+                            Some(id) if !force => infotext.push_str(&format!(
+                                "No write since last change for buffer {} (add ! to override)",
+                                id + 1
+                            )),
+                            _ => quitnow = true,
+                        }
+                    }
+                }
+                Rule::edit => {
+                    let name = get_filename(&cmd);
+                    if name != patharg {
+                        match buffers.find(&name) {
+                            Some(id) => switchbuffer = Some(id),
+                            None => match Buffer::open(&name) {
+                                Ok(buffer) => switchbuffer = Some(buffers.add(buffer)),
+                                Err(why) => infotext.push_str(&why),
+                            },
+                        }
+                    }
+                }
+                Rule::listbuffers => {
+                    let list: Vec<String> = (0..buffers.count())
+                        .map(|id| {
+                            let current = id == buffers.current();
+                            let (path, modified) = if current {
+                                (&patharg, buf != original)
                             } else {
-                                0
+                                (&buffers.get(id).path, buffers.get(id).is_modified())
                             };
-                            if cursorpos >= buf.len() {
-                                buf.insert(cursorpos, 0);
-                            }
-                            // Change the selected nibble
-                            if let Some(c) = key.to_digit(16) {
-                                buf[cursorpos] = buf[cursorpos] & mask | (c as u8) << shift;
-                            }
+                            format!(
+                                "{}{} \"{}\"{}",
+                                id + 1,
+                                if current { "%" } else { "" },
+                                path,
+                                if modified { " +" } else { "" }
+                            )
+                        })
+                        .collect();
+                    infotext.push_str(&list.join("  "));
+                }
+                Rule::nextbuffer | Rule::prevbuffer => {
+                    let count = buffers.count();
+                    switchbuffer = Some(if cmd.as_rule() == Rule::nextbuffer {
+                        (buffers.current() + 1) % count
+                    } else {
+                        (buffers.current() + count - 1) % count
+                    });
+                }
+                Rule::buffer => {
+                    let number = cmd
+                        .clone()
+                        .into_inner()
+                        .find(|inner| inner.as_rule() == Rule::buffernumber)
+                        .and_then(|number| number.as_str().parse::<usize>().ok());
+                    match number {
+                        Some(n) if n >= 1 && n <= buffers.count() => switchbuffer = Some(n - 1),
+                        _ => infotext.push_str("Buffer does not exist"),
+                    }
+                }
+                Rule::undo | Rule::redo => {
+                    // A replayed macro has not recorded its changes yet
+                    history.commit(&buf, cursorpos);
+                    let undo = cmd.as_rule() == Rule::undo;
+                    let pos = if undo {
+                        history.undo(&mut buf)
+                    } else {
+                        history.redo(&mut buf)
+                    };
+                    match pos {
+                        Some((pos, (start, oldlen, newlen))) => {
+                            marks.replaced(start, oldlen, newlen);
+                            cursorpos = clamp_pos(pos, buf.len());
                         }
-
+                        None if undo => infotext.push_str("Already at oldest change"),
+                        None => infotext.push_str("Already at newest change"),
                     }
-                    // TODO: use inner_cmd and not just "key"
-                    Rule::insertment => {
-                        // printw(&format!("Inserted: {:?}", inner_cmd.as_str()));
-                        command.pop(); // remove the just inserted thing
-                        clear = false;
-
-                        if cstate == Cursorstate::Leftnibble {
-                            // Left nibble
-                            if let Some(c) = (key as char).to_digit(16) {
-                                buf.insert(cursorpos, (c as u8) << 4);
-                                cstate = Cursorstate::Rightnibble;
+                }
+                Rule::diffget | Rule::diffput => match diff_partner(diffpair, buffers.current()) {
+                    Some(other) => {
+                        match hunk_at(diffcache.diff(&buf, &buffers.get(other).buf), cursorpos) {
+                            Some(hunk) if cmd.as_rule() == Rule::diffget => {
+                                let bytes = buffers.get(other).buf[hunk.b..hunk.b + hunk.blen].to_vec();
+                                buf.splice(hunk.a..hunk.a + hunk.alen, bytes);
+                                marks.replaced(hunk.a, hunk.alen, hunk.blen);
+                                cursorpos = clamp_pos(cursorpos, buf.len());
                             }
-                        } else if cstate == Cursorstate::Rightnibble {
-                            // Right nibble
-                            if cursorpos == buf.len() {
-                                buf.insert(cursorpos, 0);
+                            Some(hunk) => {
+                                let bytes = buf[hunk.a..hunk.a + hunk.alen].to_vec();
+                                let other = buffers.get_mut(other);
+                                other.buf.splice(hunk.b..hunk.b + hunk.blen, bytes);
+                                other.marks.replaced(hunk.b, hunk.blen, hunk.alen);
                             }
-                            if let Some(c) = (key as char).to_digit(16) {
-                                buf[cursorpos] = buf[cursorpos] & 0xF0 | c as u8;
-                                cstate = Cursorstate::Leftnibble;
-                                cursorpos += 1;
+                            None => infotext.push_str("No difference under the cursor"),
+                        }
+                    }
+                    None => infotext.push_str("Not in diff mode"),
+                },
+                Rule::split | Rule::vsplit => {
+                    let direction = if cmd.as_rule() == Rule::split {
+                        Direction::Horizontal
+                    } else {
+                        Direction::Vertical
+                    };
+                    windows.split(direction, Window { cursorpos, cstate, screenoffset, buffer: buffers.current() });
+                }
+                Rule::nextwindow | Rule::prevwindow => {
+                    let current = Window { cursorpos, cstate, screenoffset, buffer: buffers.current() };
+                    switchto = Some(windows.switch(current, cmd.as_rule() == Rule::nextwindow));
+                }
+                Rule::close => {
+                    switchto = windows.close();
+                    if switchto.is_none() {
+                        infotext.push_str("Cannot close last window");
+                    }
+                }
+                Rule::only => windows.only(Window { cursorpos, cstate, screenoffset, buffer: buffers.current() }),
+                Rule::save => save = true,
+                Rule::escape => {
+                    if mode != Mode::Normal && !typed.is_empty() {
+                        let change = if mode == Mode::Insert {
+                            Change::Insert(typed.clone())
+                        } else {
+                            Change::Overwrite(typed.clone())
+                        };
+                        // The keys were typed once already
+                        change.apply(&mut buf, &mut marks, &mut cursorpos, &mut cstate, &settings.view, changecount - 1);
+                        lastchange = Some((change, changecount));
+                    }
+                    mode = Mode::Normal;
+                    visualstart = None;
+                    if listfocus {
+                        matchlist = None;
+                        listfocus = false;
+                    }
+                }
+                Rule::markset => {
+                    marks.set(cmd.as_str().chars().next().unwrap(), cursorpos);
+                }
+                Rule::markjump | Rule::markjumpline => {
+                    match marks.get(cmd.as_str().chars().next().unwrap()) {
+                        Some(pos) => {
+                            jumplist.push(cursorpos);
+                            cursorpos = clamp_pos(pos, buf.len());
+                            if cmd.as_rule() == Rule::markjumpline {
+                                cursorpos -= cursorpos % cols; // jump to start of line
                             }
-                        } else if cstate == Cursorstate::Asciichar {
-                            buf.insert(cursorpos, key as u8);
-                            cursorpos += 1;
                         }
+                        None => infotext.push_str("Mark not set"),
+                    }
+                }
+                Rule::jumpback => {
+                    if let Some(pos) = jumplist.back(cursorpos) {
+                        cursorpos = clamp_pos(pos, buf.len());
+                    }
+                }
+                Rule::jumpforward => {
+                    if let Some(pos) = jumplist.forward() {
+                        cursorpos = clamp_pos(pos, buf.len());
                     }
-                    Rule::searchstr => {
-                        let search = inner_cmd.as_str().as_bytes();
-                        let foundpos = TwoWaySearcher::new(&search);
-                        cursorpos = foundpos.search_in(&buf).unwrap_or(cursorpos);
-                    }
-                    Rule::searchbytes => {
-                        let search = inner_cmd.as_str().as_bytes();
-                        let mut needle = vec![];
-                        for i in 0..search.len() {
-                            let nibble = match search[i] as u8 {
-                                c @ 48...57 => c - 48, // Numbers from 0 to 9
-                                b'x' => 0x10, // x is the wildcard
-                                b'X' => 0x10, // X is the wildcard
-                                c @ b'a'...b'f' => c - 87,
-                                c @ b'A'...b'F' => c - 55,
-                                _ => panic!("Should not get to this position!"),
+                }
+                Rule::goto | Rule::gotoforward | Rule::gotobackward => {
+                    match eval_first(cmd.clone(), cursorpos, buf.len()) {
+                        Some(offset) => {
+                            let target = match cmd.as_rule() {
+                                Rule::goto => offset,
+                                Rule::gotoforward => cursorpos as i64 + offset,
+                                _ => cursorpos as i64 - offset,
                             };
-                            needle.push(nibble);
+                            jumplist.push(cursorpos);
+                            // Jumps past the end stop at the last byte
+                            cursorpos = clamp_pos(cmp::max(target, 0) as usize, buf.len());
+                        }
+                        None => infotext.push_str("Invalid offset"),
+                    }
+                }
+                Rule::findall => {
+                    let needle = get_needle(cmd.clone());
+                    let positions = needle.as_ref().map_or(vec![], |n| n.find_all(&buf));
+                    if positions.is_empty() {
+                        infotext.push_str("Pattern not found");
+                        matchlist = None;
+                    } else {
+                        infotext.push_str(&format!("{} matches", positions.len()));
+                        matchlist = Some(MatchList::new(positions, needle.map_or(0, |n| n.len())));
+                        listbuflen = buf.len();
+                    }
+                    listfocus = matchlist.is_some();
+                }
+                Rule::changelist => {
+                    let hunks = side_hunks(changecache.diff(&buf, &original));
+                    if hunks.is_empty() {
+                        infotext.push_str("No changes");
+                        matchlist = None;
+                    } else {
+                        infotext.push_str(&format!("{} changes", hunks.len()));
+                        let positions = hunks.iter().map(|hunk| hunk.start).collect();
+                        let lens = hunks.iter().map(|hunk| hunk.len).collect();
+                        matchlist = Some(MatchList::with_lens("Change", positions, lens));
+                        listbuflen = buf.len();
+                    }
+                    listfocus = matchlist.is_some();
+                }
+                Rule::listopen => {
+                    listfocus = matchlist.is_some();
+                    if !listfocus {
+                        infotext.push_str("No match list");
+                    }
+                }
+                Rule::listclose => {
+                    matchlist = None;
+                    listfocus = false;
+                }
+                Rule::listnext | Rule::listprev => {
+                    if let Some(ref mut list) = matchlist {
+                        if cmd.as_rule() == Rule::listnext {
+                            list.select_next();
+                        } else {
+                            list.select_prev();
                         }
-                        cursorpos = buf.find_subset(&needle).unwrap_or(cursorpos);
-                        // endwin(); println!("Searching for: {:?}", needle ); return;
+                        jumplist.push(cursorpos);
+                        cursorpos = clamp_pos(list.current(), buf.len());
+                        infotext.push_str(&format!("{} {} of {}", list.name, list.selected + 1, list.positions.len()));
+                    } else {
+                        infotext.push_str("No match list");
                     }
+                }
+                Rule::search | Rule::hexsearch => {
+                    let from = searchstart.take().unwrap_or(cursorpos);
+                    cursorpos = from;
+                    if let Some(needle) = get_needle(cmd.clone()) {
+                        if let Some(pos) = needle.find_next(&buf, from) {
+                            jumplist.push(from);
+                            cursorpos = pos;
+                        }
+                    }
+                }
+
+                _ => (),
+            }
+
+            let mut subneedle: Option<Vec<u8>> = None;
+            let mut subreplacement = vec![];
+            let mut subglobal = false;
+            for inner_cmd in cmd.into_inner() {
+                match inner_cmd.as_rule() {
+                    // Handled with the whole change
+                    Rule::count | Rule::replacement | Rule::replayreg | Rule::fillpattern | Rule::fillbyte => (),
+                    Rule::filename | Rule::readoverwrite | Rule::buffernumber => (),
+                    Rule::shellcmd | Rule::rangeall | Rule::rangevisual => (),
+                    // Handled with the whole search
+                    Rule::searchwide | Rule::searchnocase | Rule::searchstr | Rule::searchbytes => (),
+                    Rule::subbytes => subneedle = Some(hex_to_nibbles(inner_cmd.as_str())),
+                    Rule::subtext => subneedle = Some(bytes_to_nibbles(inner_cmd.as_str().as_bytes())),
+                    Rule::replbytes => subreplacement = nibbles_to_bytes(&hex_to_nibbles(inner_cmd.as_str())),
+                    Rule::repltext => subreplacement = inner_cmd.as_str().as_bytes().to_vec(),
+                    Rule::subglobal => subglobal = true,
+                    Rule::wordsize => wordsize = inner_cmd.as_str().parse().unwrap(),
+                    Rule::view => settings.view.radix = Radix::from_name(inner_cmd.as_str()).unwrap(),
+                    Rule::offsets => settings.view.offsets = Radix::from_name(inner_cmd.as_str()).unwrap(),
+                    Rule::baseaddress => match eval_first(inner_cmd, cursorpos, buf.len()) {
+                        Some(base) if base >= 0 => settings.view.baseaddress = base as usize,
+                        _ => infotext.push_str("Invalid base address"),
+                    },
+                    Rule::group => settings.view.group = inner_cmd.as_str().parse().unwrap(),
+                    Rule::endian => settings.view.bigendian = inner_cmd.as_str() == "be",
+                    Rule::encoding => settings.view.encoding = Encoding::from_name(inner_cmd.as_str()).unwrap(),
+                    Rule::theme => {
+                        settings.theme = Theme::from_name(inner_cmd.as_str()).unwrap();
+                        settings.theme.init();
+                    }
+                    // Evaluated with the whole command
+                    Rule::expr => (),
                     Rule::linenumber => {
                         let linenr: usize = inner_cmd.as_str().parse().unwrap();
-                        cursorpos = linenr * SPALTEN; // jump to the line
+                        jumplist.push(cursorpos);
+                        cursorpos = linenr * cols; // jump to the line
                         if cursorpos > buf.len() { // detect file end
                             cursorpos = buf.len();
                         }
-                        cursorpos -= cursorpos % SPALTEN; // jump to start of line
+                        cursorpos -= cursorpos % cols; // jump to start of line
                     }
-                    Rule::escape => (),
                     Rule::gatherone => clear = false,
                     _ => {
                         command.push_str(&format!("no rule for {:?} ", inner_cmd.as_rule()));
@@ -355,55 +1285,115 @@ fn main() {
                     }
                 };
             }
+            if let Some(needle) = subneedle {
+                // Substitute in the visual selection or in the whole file
+                let (start, end) = get_selection(visualstart, cursorpos).unwrap_or((0, buf.len()));
+                let end = cmp::min(end, buf.len());
+                let positions = substitute(&mut buf, &needle, &subreplacement, start, end, subglobal);
+                for &pos in &positions {
+                    marks.replaced(pos, needle.len() / 2, subreplacement.len());
+                }
+                if let Some(&pos) = positions.last() {
+                    cursorpos = clamp_pos(pos, buf.len());
+                }
+                visualstart = None;
+                infotext.push_str(&match positions.len() {
+                    1 => "1 substitution".to_string(),
+                    n => format!("{} substitutions", n),
+                });
+            }
             if save {
+                let path = Path::new(&patharg);
                 if path.exists() {
                     let mut file = match OpenOptions::new()
                         .read(true)
                         .write(true)
                         .create(true)
-                        .open(&path) {
+                        .truncate(false)
+                        .open(path) {
                         Err(why) => {
                             panic!(
                                 "Could not open {}: {}",
                                 path.display(),
-                                Error::description(&why)
+                                why
                             )
                         }
                         Ok(file) => file,
                     };
-                    file.seek(SeekFrom::Start(0)).ok().expect(
+                    file.seek(SeekFrom::Start(0)).expect(
                         "Filepointer could not be set to 0",
                     );
-                    file.write_all(&mut buf).ok().expect(
+                    file.write_all(&buf).expect(
                         "File could not be written.",
                     );
-                    file.set_len(buf.len() as u64).ok().expect(
+                    file.set_len(buf.len() as u64).expect(
                         "File could not be set to correct lenght.",
                     );
-                    command.push_str("File saved!");
+                    infotext.push_str("File saved!");
+                    original = buf.clone();
                 } else {
-                    command.push_str("Careful, file could not be saved!");
+                    infotext.push_str("Careful, file could not be saved!");
                 }
                 // TODO: define filename during runtime
                 save = false;
             }
-            if clear {
-                command.clear();
+            // A window on another buffer switches the buffer too
+            if let Some(window) = switchto {
+                switchbuffer = Some(window.buffer);
             }
-
-            // Always move screen when cursor leaves screen
-            if cursorpos > (screenheight + screenoffset - 1) * SPALTEN - 1 {
-                screenoffset = 2 + cursorpos / SPALTEN - screenheight;
+            if let Some(id) = switchbuffer.take() {
+                if id != buffers.current() {
+                    let current = Buffer { path: patharg, buf, original, changes: changecache, marks, history, cursorpos };
+                    let next = buffers.switch(current, id);
+                    patharg = next.path;
+                    buf = next.buf;
+                    original = next.original;
+                    changecache = next.changes;
+                    marks = next.marks;
+                    history = next.history;
+                    cursorpos = clamp_pos(next.cursorpos, buf.len());
+                    // Selections and matches belong to the other buffer
+                    visualstart = None;
+                    matchlist = None;
+                    listfocus = false;
+                }
             }
-            if cursorpos < screenoffset * SPALTEN {
-                screenoffset = cursorpos / SPALTEN;
+            if let Some(window) = switchto.take() {
+                cursorpos = clamp_pos(window.cursorpos, buf.len());
+                cstate = window.cstate;
+                screenoffset = window.screenoffset;
             }
+            if clear {
+                command.clear();
+            }
+        }
 
+        // Inserted or removed bytes move the matches, the list is dropped
+        if matchlist.is_some() && buf.len() != listbuflen {
+            matchlist = None;
+            listfocus = false;
+        }
 
+        // Every command is a step of the undo history, a macro is one step
+        if mode == Mode::Normal && pending.is_empty() {
+            history.commit(&buf, cursorpos);
         }
 
-    let draw_range = get_absolute_draw_indices(buf.len(), SPALTEN, screenoffset);
-        draw(&buf[draw_range.0 .. draw_range.1], cursorpos, SPALTEN, &command, &mut debug, cstate, screenoffset);
+        // Incremental search, jump to the first match while typing
+        searchmatch = None;
+        if command.starts_with('/') {
+            let from = *searchstart.get_or_insert(cursorpos);
+            cursorpos = from;
+            if let Some(needle) = get_preview_needle(&command) {
+                if let Some(pos) = needle.find_next(&buf, from) {
+                    cursorpos = pos;
+                    searchmatch = Some((pos, pos + needle.len()));
+                }
+            }
+        } else if let Some(from) = searchstart.take() {
+            // Search was aborted, go back
+            cursorpos = from;
+        }
     }
 
     refresh();
@@ -0,0 +1,172 @@
+use std::cmp;
+
+// Byte offsets stored with the vim commands ma to mz.
+#[derive(Default)]
+pub struct Marks {
+    positions: [Option<usize>; 26],
+}
+
+impl Marks {
+    fn index(name: char) -> usize {
+        (name as u8 - b'a') as usize
+    }
+    pub fn set(&mut self, name: char, pos: usize) {
+        self.positions[Marks::index(name)] = Some(pos);
+    }
+    pub fn get(&self, name: char) -> Option<usize> {
+        self.positions[Marks::index(name)]
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.iter().all(|pos| pos.is_none())
+    }
+    // Returns the first mark inside start..end
+    pub fn find_in(&self, start: usize, end: usize) -> Option<char> {
+        self.positions
+            .iter()
+            .position(|pos| pos.is_some_and(|pos| start <= pos && pos < end))
+            .map(|i| (b'a' + i as u8) as char)
+    }
+    // Moves the marks behind pos after count bytes were inserted at pos
+    pub fn inserted(&mut self, pos: usize, count: usize) {
+        for mark in self.positions.iter_mut().flatten() {
+            if *mark >= pos {
+                *mark += count;
+            }
+        }
+    }
+    // Moves the marks behind pos after count bytes were removed at pos.
+    // Marks on removed bytes move to pos.
+    pub fn removed(&mut self, pos: usize, count: usize) {
+        for mark in self.positions.iter_mut().flatten() {
+            if *mark >= pos + count {
+                *mark -= count;
+            } else if *mark > pos {
+                *mark = pos;
+            }
+        }
+    }
+    // Moves the marks after removed bytes at pos were replaced by inserted bytes
+    pub fn replaced(&mut self, pos: usize, removed: usize, inserted: usize) {
+        if inserted >= removed {
+            self.inserted(pos + removed, inserted - removed);
+        } else {
+            self.removed(pos + inserted, removed - inserted);
+        }
+    }
+}
+
+// Positions before jumps, walked through with Ctrl-O and Ctrl-I.
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<usize>,
+    index: usize,
+}
+
+// Like vim, only remember the last 100 jumps
+const MAXJUMPS: usize = 100;
+
+impl JumpList {
+    // Remembers the position before a jump, forgets newer jumps
+    pub fn push(&mut self, pos: usize) {
+        self.jumps.truncate(self.index);
+        self.jumps.push(pos);
+        if self.jumps.len() > MAXJUMPS {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+    pub fn back(&mut self, current: usize) -> Option<usize> {
+        if self.index == 0 {
+            return None;
+        }
+        if self.index == self.jumps.len() {
+            // Remember where we come from, to be able to go forward again
+            self.jumps.push(current);
+        }
+        self.index -= 1;
+        Some(self.jumps[self.index])
+    }
+    pub fn forward(&mut self) -> Option<usize> {
+        if self.index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.jumps[self.index])
+    }
+}
+
+// Clamps a remembered position to the current buffer
+pub fn clamp_pos(pos: usize, buflen: usize) -> usize {
+    cmp::min(pos, buflen.saturating_sub(1))
+}
+
+#[test]
+fn marks_set_and_get() {
+    let mut marks = Marks::default();
+    assert!(marks.is_empty());
+    marks.set('c', 42);
+    assert_eq!(marks.get('c'), Some(42));
+    assert_eq!(marks.get('d'), None);
+    assert_eq!(marks.find_in(40, 48), Some('c'));
+    assert_eq!(marks.find_in(0, 42), None);
+}
+#[test]
+fn marks_shift_on_insert() {
+    let mut marks = Marks::default();
+    marks.set('a', 4);
+    marks.set('b', 10);
+    marks.inserted(5, 3);
+    assert_eq!(marks.get('a'), Some(4));
+    assert_eq!(marks.get('b'), Some(13));
+    marks.inserted(4, 1);
+    assert_eq!(marks.get('a'), Some(5));
+}
+#[test]
+fn marks_shift_on_remove() {
+    let mut marks = Marks::default();
+    marks.set('a', 4);
+    marks.set('b', 10);
+    marks.set('c', 6);
+    marks.removed(5, 3);
+    assert_eq!(marks.get('a'), Some(4));
+    assert_eq!(marks.get('b'), Some(7));
+    assert_eq!(marks.get('c'), Some(5));
+}
+#[test]
+fn marks_shift_on_replace() {
+    let mut marks = Marks::default();
+    marks.set('a', 4);
+    marks.set('b', 7);
+    marks.set('c', 10);
+    marks.replaced(4, 4, 6);
+    assert_eq!(marks.get('a'), Some(4));
+    assert_eq!(marks.get('b'), Some(7));
+    assert_eq!(marks.get('c'), Some(12));
+    marks.replaced(4, 6, 2);
+    assert_eq!(marks.get('a'), Some(4));
+    assert_eq!(marks.get('b'), Some(6));
+    assert_eq!(marks.get('c'), Some(8));
+}
+#[test]
+fn jumplist_back_and_forward() {
+    let mut jumps = JumpList::default();
+    assert_eq!(jumps.back(0), None);
+    jumps.push(0);
+    jumps.push(100);
+    assert_eq!(jumps.back(200), Some(100));
+    assert_eq!(jumps.back(100), Some(0));
+    assert_eq!(jumps.back(0), None);
+    assert_eq!(jumps.forward(), Some(100));
+    assert_eq!(jumps.forward(), Some(200));
+    assert_eq!(jumps.forward(), None);
+}
+#[test]
+fn jumplist_push_forgets_newer() {
+    let mut jumps = JumpList::default();
+    jumps.push(0);
+    jumps.push(100);
+    assert_eq!(jumps.back(200), Some(100));
+    jumps.push(100);
+    assert_eq!(jumps.forward(), None);
+    assert_eq!(jumps.back(50), Some(100));
+}
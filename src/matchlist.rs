@@ -0,0 +1,146 @@
+extern crate ncurses;
+use ncurses::*;
+use view::View;
+use std::cmp;
+
+// Maximum number of matches shown at once
+const MAXROWS: usize = 8;
+// Bytes shown before each match
+const CONTEXT: usize = 4;
+// Bytes shown per match, including the context
+const SHOWN: usize = 16;
+
+// All matches of a search or all changes, shown below the hex view like vim's quickfix list.
+pub struct MatchList {
+    pub positions: Vec<usize>,
+    // Bytes covered by each entry
+    pub lens: Vec<usize>,
+    // Shown in the title, like "Match 1 of 3"
+    pub name: &'static str,
+    pub selected: usize,
+    // First match shown in the list
+    pub top: usize,
+}
+
+impl MatchList {
+    pub fn new(positions: Vec<usize>, len: usize) -> MatchList {
+        let lens = vec![len; positions.len()];
+        MatchList::with_lens("Match", positions, lens)
+    }
+    pub fn with_lens(name: &'static str, positions: Vec<usize>, lens: Vec<usize>) -> MatchList {
+        MatchList {
+            positions,
+            lens,
+            name,
+            selected: 0,
+            top: 0,
+        }
+    }
+    pub fn current(&self) -> usize {
+        self.positions[self.selected]
+    }
+    pub fn select(&mut self, index: usize) {
+        self.selected = cmp::min(index, self.positions.len() - 1);
+        // Keep the selected match visible
+        if self.selected < self.top {
+            self.top = self.selected;
+        }
+        if self.selected >= self.top + MAXROWS {
+            self.top = self.selected + 1 - MAXROWS;
+        }
+    }
+    pub fn select_next(&mut self) {
+        let next = self.selected + 1;
+        self.select(next);
+    }
+    pub fn select_prev(&mut self) {
+        let prev = self.selected.saturating_sub(1);
+        self.select(prev);
+    }
+    // Screen rows taken by the list, including its title
+    pub fn height(&self) -> usize {
+        cmp::min(self.positions.len(), MAXROWS) + 1
+    }
+}
+
+pub fn draw_matchlist(list: &MatchList, buf: &[u8], row: usize, focus: bool, view: &View) {
+    mv(row as i32, 0);
+    attron(A_BOLD());
+    printw(&format!(
+        "-- {} {} of {} --",
+        list.name,
+        list.selected + 1,
+        list.positions.len()
+    ));
+    attroff(A_BOLD());
+    printw("\n");
+
+    let shown = &list.positions[list.top..cmp::min(list.top + MAXROWS, list.positions.len())];
+    for (i, &pos) in shown.iter().enumerate() {
+        let selected = list.top + i == list.selected;
+        let len = list.lens[list.top + i];
+        if selected && focus {
+            attron(COLOR_PAIR(1) | A_STANDOUT());
+        }
+        printw(&format!("{}: ", view.format_offset(pos, buf.len())));
+        if selected && focus {
+            attroff(COLOR_PAIR(1) | A_STANDOUT());
+        }
+        printw(" ");
+
+        let start = pos.saturating_sub(CONTEXT);
+        let end = cmp::min(start + SHOWN, buf.len());
+        let in_match = |p: usize| pos <= p && p < pos + len;
+        for p in start..start + SHOWN {
+            if let Some(c) = buf.get(p) {
+                if in_match(p) {
+                    attron(A_REVERSE());
+                }
+                printw(&format!("{:02X}", c));
+                if in_match(p) {
+                    attroff(A_REVERSE());
+                }
+                printw(" ");
+            } else {
+                printw("   ");
+            }
+        }
+        printw(" ");
+        for (p, &c) in buf.iter().enumerate().take(end).skip(start) {
+            if in_match(p) {
+                attron(A_REVERSE());
+            }
+            match c {
+                32..=126 => addch(c as chtype),
+                _ => addch('.' as chtype),
+            };
+            if in_match(p) {
+                attroff(A_REVERSE());
+            }
+        }
+        printw("\n");
+    }
+}
+
+#[test]
+fn select_keeps_selected_visible() {
+    let mut list = MatchList::new((0..20).collect(), 1);
+    list.select(12);
+    assert_eq!(list.top, 5);
+    list.select(2);
+    assert_eq!(list.top, 2);
+}
+#[test]
+fn select_stops_at_ends() {
+    let mut list = MatchList::new(vec![4, 8], 1);
+    list.select_prev();
+    assert_eq!(list.current(), 4);
+    list.select_next();
+    list.select_next();
+    assert_eq!(list.current(), 8);
+}
+#[test]
+fn height_is_limited() {
+    assert_eq!(MatchList::new(vec![1, 2], 1).height(), 3);
+    assert_eq!(MatchList::new((0..20).collect(), 1).height(), MAXROWS + 1);
+}
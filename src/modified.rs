@@ -0,0 +1,70 @@
+// A region of the buffer which differs from the loaded file.
+// len bytes at start replaced origlen bytes of the original.
+#[derive(PartialEq, Debug)]
+pub struct Hunk {
+    pub start: usize,
+    pub len: usize,
+    pub origlen: usize,
+}
+
+impl Hunk {
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+// The hunks are sorted, so the hunk of a position is found by bisection
+pub fn is_modified(hunks: &[Hunk], pos: usize) -> bool {
+    let i = hunks.partition_point(|hunk| hunk.end() <= pos);
+    hunks.get(i).is_some_and(|hunk| hunk.start <= pos)
+}
+
+// Start of the next hunk after pos
+pub fn next_hunk(hunks: &[Hunk], pos: usize) -> Option<usize> {
+    hunks.iter().map(|hunk| hunk.start).find(|&start| start > pos)
+}
+
+// Start of the previous hunk before pos
+pub fn prev_hunk(hunks: &[Hunk], pos: usize) -> Option<usize> {
+    hunks.iter().rev().map(|hunk| hunk.start).find(|&start| start < pos)
+}
+
+// The modified regions, like the cached ones of a buffer
+#[cfg(test)]
+fn find_hunks(original: &[u8], buf: &[u8]) -> Vec<Hunk> {
+    use diff::{diff, side_hunks};
+    side_hunks(&diff(buf, original))
+}
+
+#[test]
+fn overwritten_bytes() {
+    let original = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    let buf = [0xFF, 0x11, 0xFF, 0xFF, 0x44, 0x55];
+    let hunks = find_hunks(&original, &buf);
+    assert_eq!(hunks, [Hunk { start: 0, len: 1, origlen: 1 }, Hunk { start: 2, len: 2, origlen: 2 }]);
+    assert!(is_modified(&hunks, 3));
+    assert!(!is_modified(&hunks, 1));
+    assert!(!is_modified(&hunks, 4));
+    assert_eq!(next_hunk(&hunks, 0), Some(2));
+    assert_eq!(prev_hunk(&hunks, 2), Some(0));
+    assert_eq!(next_hunk(&hunks, 2), None);
+}
+#[test]
+fn inserted_and_removed_bytes() {
+    let original = [0x00, 0x11, 0x11, 0x22];
+    assert_eq!(find_hunks(&original, &[0x00, 0x11, 0xAA, 0x11, 0x22]), [Hunk { start: 2, len: 1, origlen: 0 }]);
+    assert_eq!(find_hunks(&original, &[0x00, 0x11, 0x22]), [Hunk { start: 2, len: 0, origlen: 1 }]);
+    assert_eq!(find_hunks(&original, &original), []);
+    assert_eq!(find_hunks(&[], &[0x12]), [Hunk { start: 0, len: 1, origlen: 0 }]);
+}
+#[test]
+fn edits_far_apart() {
+    let original: Vec<u8> = (0..64).collect();
+    let mut buf = original.clone();
+    buf.remove(0);
+    buf[62] = 0xFF;
+    assert_eq!(find_hunks(&original, &buf), [
+        Hunk { start: 0, len: 0, origlen: 1 },
+        Hunk { start: 62, len: 1, origlen: 1 },
+    ]);
+}
@@ -0,0 +1,141 @@
+use std::cmp;
+
+// Words are groups of wordsize bytes, aligned to the start of the file.
+
+// Start of the next word
+pub fn word_forward(pos: usize, wordsize: usize, buflen: usize) -> usize {
+    let next = (pos / wordsize + 1) * wordsize;
+    if next < buflen {
+        next
+    } else {
+        pos
+    }
+}
+
+// Start of the word, or of the previous word when already at the start
+pub fn word_backward(pos: usize, wordsize: usize) -> usize {
+    if pos.is_multiple_of(wordsize) {
+        pos.saturating_sub(wordsize)
+    } else {
+        pos - pos % wordsize
+    }
+}
+
+// End of the word, or of the next word when already at the end
+pub fn word_end(pos: usize, wordsize: usize, buflen: usize) -> usize {
+    let mut end = (pos / wordsize + 1) * wordsize - 1;
+    if end == pos {
+        end += wordsize;
+    }
+    cmp::min(end, cmp::max(buflen, pos + 1) - 1)
+}
+
+// Last byte of the line at pos, or of the buffer on a short last line
+pub fn line_end(pos: usize, cols: usize, buflen: usize) -> usize {
+    cmp::min(pos - pos % cols + cols - 1, buflen.saturating_sub(1))
+}
+
+// Start of the last line, 0 in an empty buffer
+pub fn last_line(buflen: usize, cols: usize) -> usize {
+    let last = buflen.saturating_sub(1);
+    last - last % cols
+}
+
+// Minimum length of a printable run, like the strings tool
+pub const MINPRINTABLE: usize = 4;
+
+pub fn is_printable(c: u8) -> bool {
+    (32..=126).contains(&c)
+}
+
+// A run starts at pos, when the next minlen bytes match, but the byte before does not
+fn is_run_start<F: Fn(u8) -> bool>(buf: &[u8], pos: usize, minlen: usize, matches: &F) -> bool {
+    pos + minlen <= buf.len()
+        && buf[pos..pos + minlen].iter().all(|&c| matches(c))
+        && (pos == 0 || !matches(buf[pos - 1]))
+}
+
+// Start of the next run of bytes after pos
+pub fn next_run<F: Fn(u8) -> bool>(buf: &[u8], pos: usize, minlen: usize, matches: F) -> Option<usize> {
+    (pos + 1..buf.len()).find(|&p| is_run_start(buf, p, minlen, &matches))
+}
+
+// Start of the previous run of bytes before pos
+pub fn prev_run<F: Fn(u8) -> bool>(buf: &[u8], pos: usize, minlen: usize, matches: F) -> Option<usize> {
+    (0..pos).rev().find(|&p| is_run_start(buf, p, minlen, &matches))
+}
+
+// Next byte after pos, which differs from the byte at pos
+pub fn next_different(buf: &[u8], pos: usize) -> Option<usize> {
+    let c = *buf.get(pos)?;
+    (pos + 1..buf.len()).find(|&p| buf[p] != c)
+}
+
+// Previous byte before pos, which differs from the byte at pos
+pub fn prev_different(buf: &[u8], pos: usize) -> Option<usize> {
+    let c = *buf.get(pos)?;
+    (0..pos).rev().find(|&p| buf[p] != c)
+}
+
+#[test]
+fn word_forward_aligned() {
+    assert_eq!(word_forward(0, 4, 16), 4);
+    assert_eq!(word_forward(5, 4, 16), 8);
+    assert_eq!(word_forward(13, 4, 16), 13);
+    assert_eq!(word_forward(1, 8, 16), 8);
+}
+#[test]
+fn word_backward_aligned() {
+    assert_eq!(word_backward(5, 4), 4);
+    assert_eq!(word_backward(4, 4), 0);
+    assert_eq!(word_backward(0, 4), 0);
+    assert_eq!(word_backward(3, 2), 2);
+}
+#[test]
+fn word_end_aligned() {
+    assert_eq!(word_end(0, 4, 16), 3);
+    assert_eq!(word_end(3, 4, 16), 7);
+    assert_eq!(word_end(13, 4, 16), 15);
+    assert_eq!(word_end(15, 4, 16), 15);
+    assert_eq!(word_end(12, 4, 14), 13);
+}
+#[test]
+fn line_motions_after_truncate() {
+    let mut buf = vec![0x00; 40];
+    assert_eq!(line_end(20, 16, buf.len()), 31);
+    assert_eq!(line_end(36, 16, buf.len()), 39);
+    assert_eq!(last_line(buf.len(), 16), 32);
+    buf.truncate(0);
+    assert_eq!(line_end(0, 16, buf.len()), 0);
+    assert_eq!(last_line(buf.len(), 16), 0);
+}
+#[test]
+fn next_run_skips_current_run() {
+    let buf = [0x00, 0x01, 0x02, 0x00, 0x00, 0x03];
+    assert_eq!(next_run(&buf, 0, 1, |c| c != 0x00), Some(1));
+    assert_eq!(next_run(&buf, 1, 1, |c| c != 0x00), Some(5));
+    assert_eq!(next_run(&buf, 5, 1, |c| c != 0x00), None);
+}
+#[test]
+fn prev_run_finds_start() {
+    let buf = [0x01, 0x02, 0xFF, 0xFF, 0x03, 0x04];
+    assert_eq!(prev_run(&buf, 5, 1, |c| c != 0xFF), Some(4));
+    assert_eq!(prev_run(&buf, 4, 1, |c| c != 0xFF), Some(0));
+    assert_eq!(prev_run(&buf, 0, 1, |c| c != 0xFF), None);
+}
+#[test]
+fn printable_run_needs_min_length() {
+    let buf = b"\0ab\0\0abcd\0";
+    assert_eq!(next_run(buf, 0, MINPRINTABLE, is_printable), Some(5));
+    assert_eq!(prev_run(buf, 9, MINPRINTABLE, is_printable), Some(5));
+    assert_eq!(prev_run(buf, 5, MINPRINTABLE, is_printable), None);
+}
+#[test]
+fn different_bytes() {
+    let buf = [0x00, 0x00, 0x00, 0x05, 0x00];
+    assert_eq!(next_different(&buf, 0), Some(3));
+    assert_eq!(next_different(&buf, 3), Some(4));
+    assert_eq!(prev_different(&buf, 2), None);
+    assert_eq!(prev_different(&buf, 4), Some(3));
+    assert_eq!(next_different(&buf, 9), None);
+}
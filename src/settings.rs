@@ -0,0 +1,9 @@
+use theme::Theme;
+use view::View;
+
+// Options of the view, changed with :set
+#[derive(Default)]
+pub struct Settings {
+    pub theme: Theme,
+    pub view: View,
+}
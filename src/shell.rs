@@ -0,0 +1,67 @@
+extern crate ncurses;
+use ncurses::*;
+use std::io;
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+use std::thread;
+
+// Sends the bytes through a shell command and returns its output.
+// Fails with the first line of stderr, when the command fails.
+pub fn filter(command: &str, input: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|why| why.to_string())?;
+    let mut stdin = child.stdin.take().unwrap();
+    // Write from a thread, the command blocks when its output is not read
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().map_err(|why| why.to_string())?;
+    // The command does not need to read all input
+    let _ = writer.join();
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(stderr.lines().next().map_or(output.status.to_string(), |line| line.to_string()))
+    }
+}
+
+// Quotes a path for sh, a ' inside is closed, escaped and opened again
+fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+// Runs a shell command on the terminal, % is replaced by the quoted path.
+// Like vim, waits for enter before returning to the editor.
+pub fn run_command(command: &str, path: &str) {
+    def_prog_mode();
+    endwin();
+    println!();
+    let _ = Command::new("sh").arg("-c").arg(command.replace('%', &shell_quote(path))).status();
+    print!("\nPress ENTER to continue");
+    let _ = io::stdout().flush();
+    let _ = io::stdin().read_line(&mut String::new());
+    reset_prog_mode();
+    refresh();
+}
+
+#[test]
+fn filter_through_command() {
+    assert_eq!(filter("tr a-z A-Z", b"hexdino".to_vec()), Ok(b"HEXDINO".to_vec()));
+    assert_eq!(filter("head -c 2", vec![0xAA; 100_000]), Ok(vec![0xAA, 0xAA]));
+}
+#[test]
+fn filter_reports_failure() {
+    assert_eq!(filter("echo broken >&2; exit 1", vec![]), Err("broken".to_string()));
+}
+#[test]
+fn quoted_path_is_one_word() {
+    let path = "a b;'c";
+    assert_eq!(shell_quote(path), "'a b;'\\''c'");
+    let command = format!("printf %s {}", shell_quote(path));
+    assert_eq!(filter(&command, vec![]), Ok(path.as_bytes().to_vec()));
+}
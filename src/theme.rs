@@ -0,0 +1,96 @@
+extern crate ncurses;
+use ncurses::*;
+
+// Bytes are colored by their class in both panes
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ByteClass {
+    Nul,
+    Printable,
+    Whitespace,
+    Control,
+    Ff,
+    High,
+}
+
+pub fn classify(c: u8) -> ByteClass {
+    match c {
+        0x00 => ByteClass::Nul,
+        b'\t' | b'\n' | 0x0B | 0x0C | b'\r' | b' ' => ByteClass::Whitespace,
+        0x21..=0x7E => ByteClass::Printable,
+        0xFF => ByteClass::Ff,
+        0x80..=0xFE => ByteClass::High,
+        _ => ByteClass::Control,
+    }
+}
+
+// Selected with :set theme=
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+pub enum Theme {
+    // No byte class colors
+    Plain,
+    #[default]
+    Dark,
+    Light,
+}
+
+// Color pair of the cursor, the byte classes follow
+const CLASSPAIRS: i16 = 2;
+// Color pair of bytes which differ from the file
+const MODIFIEDPAIR: i16 = CLASSPAIRS + 6;
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "plain" => Some(Theme::Plain),
+            "dark" => Some(Theme::Dark),
+            "light" => Some(Theme::Light),
+            _ => None,
+        }
+    }
+    // Foreground colors of the byte classes, on the default background
+    fn colors(self) -> Option<[i16; 6]> {
+        match self {
+            Theme::Plain => None,
+            Theme::Dark => Some([COLOR_BLUE, COLOR_CYAN, COLOR_GREEN, COLOR_MAGENTA, COLOR_RED, COLOR_YELLOW]),
+            Theme::Light => Some([COLOR_BLUE, COLOR_BLACK, COLOR_GREEN, COLOR_MAGENTA, COLOR_RED, COLOR_CYAN]),
+        }
+    }
+    // Defines the color pairs of the byte classes
+    pub fn init(self) {
+        if let Some(colors) = self.colors() {
+            for (i, &color) in colors.iter().enumerate() {
+                init_pair(CLASSPAIRS + i as i16, color, -1);
+            }
+            init_pair(MODIFIEDPAIR, COLOR_BLACK, COLOR_YELLOW);
+        }
+    }
+    // Color pair attribute of a byte, None without colors
+    pub fn attr(self, c: u8) -> Option<attr_t> {
+        self.colors().map(|_| COLOR_PAIR(CLASSPAIRS + classify(c) as i16))
+    }
+    // Attribute of modified bytes, they are bold without colors
+    pub fn modified_attr(self) -> attr_t {
+        match self.colors() {
+            Some(_) => COLOR_PAIR(MODIFIEDPAIR),
+            None => A_BOLD(),
+        }
+    }
+}
+
+#[test]
+fn classify_bytes() {
+    assert_eq!(classify(0x00), ByteClass::Nul);
+    assert_eq!(classify(b'A'), ByteClass::Printable);
+    assert_eq!(classify(b' '), ByteClass::Whitespace);
+    assert_eq!(classify(b'\n'), ByteClass::Whitespace);
+    assert_eq!(classify(0x1B), ByteClass::Control);
+    assert_eq!(classify(0x7F), ByteClass::Control);
+    assert_eq!(classify(0xFF), ByteClass::Ff);
+    assert_eq!(classify(0x80), ByteClass::High);
+}
+#[test]
+fn theme_names() {
+    assert_eq!(Theme::from_name("light"), Some(Theme::Light));
+    assert_eq!(Theme::from_name("solarized"), None);
+    assert_eq!(Theme::Plain.attr(0x00), None);
+}
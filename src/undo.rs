@@ -0,0 +1,118 @@
+use diff::changed_range;
+
+// Bytes replaced at start, with the cursor before the change
+struct Edit {
+    start: usize,
+    old: Vec<u8>,
+    new: Vec<u8>,
+    cursorpos: usize,
+}
+
+// Undo with u and redo with Ctrl-R. Every command is one step, like a whole insert.
+// The changes are found by comparing with the buffer after the last step.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    last: Vec<u8>,
+    // Cursor after the last step, where the next change starts
+    cursorpos: usize,
+}
+
+impl History {
+    pub fn new(buf: &[u8]) -> History {
+        History {
+            last: buf.to_vec(),
+            ..History::default()
+        }
+    }
+    // Records the changes since the last step as a new step
+    pub fn commit(&mut self, buf: &[u8], cursorpos: usize) {
+        if buf != &self.last[..] {
+            let (start, oldend, newend) = changed_range(&self.last, buf);
+            let edit = Edit {
+                start,
+                old: self.last[start..oldend].to_vec(),
+                new: buf[start..newend].to_vec(),
+                cursorpos: self.cursorpos,
+            };
+            self.last.splice(start..oldend, edit.new.iter().cloned());
+            self.undo.push(edit);
+            self.redo.clear();
+        }
+        self.cursorpos = cursorpos;
+    }
+    // Reverts the last step and returns the cursor before it, with the start of the
+    // replaced bytes and their length before and after, to move the marks along
+    pub fn undo(&mut self, buf: &mut Vec<u8>) -> Option<(usize, (usize, usize, usize))> {
+        let edit = self.undo.pop()?;
+        let end = edit.start + edit.new.len();
+        buf.splice(edit.start..end, edit.old.iter().cloned());
+        self.last.splice(edit.start..end, edit.old.iter().cloned());
+        self.cursorpos = edit.cursorpos;
+        let replaced = (edit.start, edit.new.len(), edit.old.len());
+        self.redo.push(edit);
+        Some((self.cursorpos, replaced))
+    }
+    // Repeats the last undone step and returns the start of the change, with the replaced bytes like undo
+    pub fn redo(&mut self, buf: &mut Vec<u8>) -> Option<(usize, (usize, usize, usize))> {
+        let edit = self.redo.pop()?;
+        let end = edit.start + edit.old.len();
+        buf.splice(edit.start..end, edit.new.iter().cloned());
+        self.last.splice(edit.start..end, edit.new.iter().cloned());
+        self.cursorpos = edit.start;
+        let replaced = (edit.start, edit.old.len(), edit.new.len());
+        self.undo.push(edit);
+        Some((self.cursorpos, replaced))
+    }
+}
+
+#[test]
+fn undo_and_redo_steps() {
+    let mut buf = vec![0x00, 0x11, 0x22, 0x33];
+    let mut history = History::new(&buf);
+    buf[1] = 0xFF;
+    history.commit(&buf, 1);
+    buf.insert(3, 0xAA);
+    buf.insert(3, 0xBB);
+    history.commit(&buf, 4);
+    // Nothing changed, no new step
+    history.commit(&buf, 0);
+    assert_eq!(history.undo(&mut buf), Some((1, (3, 2, 0))));
+    assert_eq!(buf, [0x00, 0xFF, 0x22, 0x33]);
+    assert_eq!(history.undo(&mut buf), Some((0, (1, 1, 1))));
+    assert_eq!(buf, [0x00, 0x11, 0x22, 0x33]);
+    assert_eq!(history.undo(&mut buf), None);
+    assert_eq!(history.redo(&mut buf), Some((1, (1, 1, 1))));
+    assert_eq!(buf, [0x00, 0xFF, 0x22, 0x33]);
+}
+#[test]
+fn change_drops_redo() {
+    let mut buf = vec![0x00; 4];
+    let mut history = History::new(&buf);
+    buf.truncate(1);
+    history.commit(&buf, 0);
+    history.undo(&mut buf);
+    buf.push(0x01);
+    history.commit(&buf, 0);
+    assert_eq!(history.redo(&mut buf), None);
+    assert_eq!(history.undo(&mut buf).map(|(pos, _)| pos), Some(0));
+    assert_eq!(buf, [0x00; 4]);
+}
+#[test]
+fn undo_moves_marks_back() {
+    use marks::Marks;
+    let mut buf = vec![0x00, 0x11, 0x22];
+    let mut history = History::new(&buf);
+    let mut marks = Marks::default();
+    marks.set('a', 2);
+    buf.splice(1..1, vec![0xAA, 0xBB]);
+    marks.inserted(1, 2);
+    history.commit(&buf, 1);
+    let (_, (start, oldlen, newlen)) = history.undo(&mut buf).unwrap();
+    marks.replaced(start, oldlen, newlen);
+    assert_eq!(marks.get('a'), Some(2));
+    let (_, (start, oldlen, newlen)) = history.redo(&mut buf).unwrap();
+    marks.replaced(start, oldlen, newlen);
+    assert_eq!(marks.get('a'), Some(4));
+}
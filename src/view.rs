@@ -0,0 +1,223 @@
+use Cursorstate;
+use encoding::Encoding;
+use std::cmp;
+
+// Number base of the cells in the hex pane and of the offsets
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Radix {
+    Hex,
+    Dec,
+    Oct,
+    Bin,
+}
+
+impl Radix {
+    pub fn from_name(name: &str) -> Option<Radix> {
+        match name {
+            "hex" => Some(Radix::Hex),
+            "dec" => Some(Radix::Dec),
+            "oct" => Some(Radix::Oct),
+            "bin" => Some(Radix::Bin),
+            _ => None,
+        }
+    }
+    fn base(self) -> u64 {
+        match self {
+            Radix::Hex => 16,
+            Radix::Dec => 10,
+            Radix::Oct => 8,
+            Radix::Bin => 2,
+        }
+    }
+    // Digits needed for the value
+    fn digits(self, value: u64) -> usize {
+        let mut digits = 1;
+        let mut rest = value / self.base();
+        while rest > 0 {
+            digits += 1;
+            rest /= self.base();
+        }
+        digits
+    }
+    fn format(self, value: u64, width: usize) -> String {
+        match self {
+            Radix::Hex => format!("{:0width$X}", value, width = width),
+            Radix::Dec => format!("{:0width$}", value, width = width),
+            Radix::Oct => format!("{:0width$o}", value, width = width),
+            Radix::Bin => format!("{:0width$b}", value, width = width),
+        }
+    }
+}
+
+// How bytes are shown, changed with :set view=, group=, endian=, enc=, offset= and base=.
+// A cell of group bytes is shown as one number in the hex pane.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct View {
+    pub radix: Radix,
+    pub group: usize,
+    pub bigendian: bool,
+    // Of the text pane
+    pub encoding: Encoding,
+    // Of the offset column, the base address is added to the shown offsets
+    pub offsets: Radix,
+    pub baseaddress: usize,
+}
+
+impl Default for View {
+    fn default() -> View {
+        View {
+            radix: Radix::Hex,
+            group: 1,
+            bigendian: false,
+            encoding: Encoding::default(),
+            offsets: Radix::Hex,
+            baseaddress: 0,
+        }
+    }
+}
+
+// Columns of a line besides the cells and the offset: mark gutter and the separators
+const LINEOVERHEAD: usize = 2 + 2 + 1 + 1;
+// The offset column only grows beyond 4 GiB in hex
+const MINOFFSETWIDTH: usize = 8;
+
+impl View {
+    // Two hex digits per byte, edited by nibbles
+    pub fn is_plain(&self) -> bool {
+        self.radix == Radix::Hex && self.group == 1
+    }
+    fn base(&self) -> u64 {
+        self.radix.base()
+    }
+    fn max(&self) -> u64 {
+        u64::MAX >> (64 - 8 * self.group)
+    }
+    // Digits of a cell
+    pub fn width(&self) -> usize {
+        self.radix.digits(self.max())
+    }
+    // Digits of the offset column, enough for the addresses up to the end of the buffer
+    pub fn offset_width(&self, buflen: usize) -> usize {
+        cmp::max(self.offsets.digits((self.baseaddress + buflen) as u64), MINOFFSETWIDTH)
+    }
+    pub fn format_offset(&self, pos: usize, buflen: usize) -> String {
+        self.offsets.format((self.baseaddress + pos) as u64, self.offset_width(buflen))
+    }
+    // Bytes per line, at most 16 and as many as fit into the window
+    pub fn bytes_per_row(&self, screenwidth: usize, buflen: usize) -> usize {
+        let mut cols = 16;
+        while cols > self.group {
+            let cells = cols / self.group;
+            if LINEOVERHEAD + self.offset_width(buflen) + cells * (self.width() + 1) + cols <= screenwidth {
+                break;
+            }
+            cols /= 2;
+        }
+        cols
+    }
+    // Value of the cell at pos, bytes after the end are 0
+    pub fn read(&self, buf: &[u8], pos: usize) -> u64 {
+        let mut value = 0;
+        for i in 0..self.group {
+            let byte = if self.bigendian { i } else { self.group - 1 - i };
+            value = value << 8 | *buf.get(pos + byte).unwrap_or(&0) as u64;
+        }
+        value
+    }
+    // Writes the cell at pos, the buffer grows when the cell reaches past the end
+    pub fn write(&self, buf: &mut Vec<u8>, pos: usize, value: u64) {
+        if pos + self.group > buf.len() {
+            buf.resize(pos + self.group, 0);
+        }
+        for i in 0..self.group {
+            let byte = if self.bigendian { self.group - 1 - i } else { i };
+            buf[pos + byte] = (value >> (8 * i)) as u8;
+        }
+    }
+    pub fn format(&self, value: u64) -> String {
+        self.radix.format(value, self.width())
+    }
+    // Changes a digit of a value, the first digit is the most significant.
+    // None when the key is no digit or the value gets too large.
+    pub fn with_digit(&self, value: u64, digit: usize, key: char) -> Option<u64> {
+        let base = self.base() as u128;
+        let new = key.to_digit(self.base() as u32)? as u128;
+        let place = base.pow((self.width() - 1 - digit) as u32);
+        let old = (value as u128 / place) % base;
+        let value = value as u128 - old * place + new * place;
+        if value > self.max() as u128 {
+            None
+        } else {
+            Some(value as u64)
+        }
+    }
+    // Adapts the cursor state to the view, nibbles in the plain view and digits otherwise
+    pub fn cursorstate(&self, cstate: Cursorstate) -> Cursorstate {
+        match cstate {
+            Cursorstate::Asciichar => cstate,
+            Cursorstate::Digit(_) if self.is_plain() => Cursorstate::Leftnibble,
+            Cursorstate::Digit(digit) => Cursorstate::Digit(cmp::min(digit, self.width() - 1)),
+            _ if self.is_plain() => cstate,
+            _ => Cursorstate::Digit(0),
+        }
+    }
+}
+
+#[test]
+fn cell_widths() {
+    let mut view = View::default();
+    assert_eq!(view.width(), 2);
+    view.radix = Radix::Dec;
+    assert_eq!(view.width(), 3);
+    view.group = 8;
+    assert_eq!(view.width(), 20);
+    view.radix = Radix::Bin;
+    view.group = 2;
+    assert_eq!(view.width(), 16);
+    view.radix = Radix::Oct;
+    view.group = 1;
+    assert_eq!(view.width(), 3);
+}
+#[test]
+fn read_and_write_endianness() {
+    let mut view = View { radix: Radix::Hex, group: 4, bigendian: false, ..View::default() };
+    let mut buf = vec![0x11, 0x22, 0x33, 0x44];
+    assert_eq!(view.read(&buf, 0), 0x44332211);
+    assert_eq!(view.format(view.read(&buf, 0)), "44332211");
+    view.bigendian = true;
+    assert_eq!(view.read(&buf, 0), 0x11223344);
+    view.write(&mut buf, 2, 0xAABBCCDD);
+    assert_eq!(buf, [0x11, 0x22, 0xAA, 0xBB, 0xCC, 0xDD]);
+}
+#[test]
+fn digits_are_checked() {
+    let view = View { radix: Radix::Dec, group: 1, bigendian: false, ..View::default() };
+    assert_eq!(view.with_digit(55, 0, '2'), Some(255));
+    assert_eq!(view.with_digit(55, 0, '3'), None);
+    assert_eq!(view.with_digit(55, 2, 'a'), None);
+    let view = View { radix: Radix::Bin, group: 1, bigendian: false, ..View::default() };
+    assert_eq!(view.with_digit(0, 0, '1'), Some(0x80));
+    assert_eq!(view.format(5), "00000101");
+}
+#[test]
+fn rows_fit_the_screen() {
+    let mut view = View::default();
+    assert_eq!(view.bytes_per_row(80, 0), 16);
+    assert_eq!(view.bytes_per_row(40, 0), 4);
+    view.radix = Radix::Bin;
+    assert_eq!(view.bytes_per_row(200, 0), 16);
+    assert_eq!(view.bytes_per_row(80, 0), 4);
+    view.group = 8;
+    assert_eq!(view.bytes_per_row(80, 0), 8);
+}
+#[test]
+fn offset_column_grows() {
+    let mut view = View::default();
+    assert_eq!(view.format_offset(0x10, 0x100), "00000010");
+    assert_eq!(view.format_offset(0x10, 0x1_0000_0000), "000000010");
+    view.offsets = Radix::Dec;
+    view.baseaddress = 0x0800_0000;
+    assert_eq!(view.format_offset(1, 0x100), "134217729");
+    view.offsets = Radix::Oct;
+    assert_eq!(view.format_offset(0, 0), "1000000000");
+}
@@ -0,0 +1,245 @@
+extern crate ncurses;
+use ncurses::*;
+use Cursorstate;
+
+// A viewport on a buffer with its own cursor and scroll position.
+// The state of the current window is kept by the editor while it is current.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Window {
+    pub cursorpos: usize,
+    pub cstate: Cursorstate,
+    pub screenoffset: usize,
+    pub buffer: usize,
+}
+
+// Part of the screen
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Region {
+    pub top: usize,
+    pub left: usize,
+    pub rows: usize,
+    pub width: usize,
+}
+
+// :split stacks windows, :vsplit puts them side by side
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+// Windows split the screen like in vim, the splits are nested
+enum Layout {
+    Window(usize),
+    Split(Direction, Vec<Layout>),
+}
+
+pub struct Windows {
+    windows: Vec<Window>,
+    layout: Layout,
+    current: usize,
+}
+
+impl Layout {
+    fn is_window(&self, id: usize) -> bool {
+        match *self {
+            Layout::Window(window) => window == id,
+            _ => false,
+        }
+    }
+    // Windows in screen order, left to right and top to bottom
+    fn order(&self, ids: &mut Vec<usize>) {
+        match *self {
+            Layout::Window(id) => ids.push(id),
+            Layout::Split(_, ref children) => children.iter().for_each(|child| child.order(ids)),
+        }
+    }
+    // Puts the new window before the window id, a split in the same direction gets one more window
+    fn split(&mut self, id: usize, new: usize, direction: Direction) -> bool {
+        match *self {
+            Layout::Window(window) if window == id => {
+                *self = Layout::Split(direction, vec![Layout::Window(new), Layout::Window(id)]);
+                true
+            }
+            Layout::Window(_) => false,
+            Layout::Split(dir, ref mut children) => {
+                if dir == direction {
+                    if let Some(i) = children.iter().position(|child| child.is_window(id)) {
+                        children.insert(i, Layout::Window(new));
+                        return true;
+                    }
+                }
+                children.iter_mut().any(|child| child.split(id, new, direction))
+            }
+        }
+    }
+    // Removes the window, a split with a single window left is replaced by it
+    fn remove(&mut self, id: usize) -> bool {
+        let removed = match *self {
+            Layout::Window(_) => return false,
+            Layout::Split(_, ref mut children) => match children.iter().position(|child| child.is_window(id)) {
+                Some(i) => {
+                    children.remove(i);
+                    true
+                }
+                None => children.iter_mut().any(|child| child.remove(id)),
+            },
+        };
+        let single = match *self {
+            Layout::Split(_, ref mut children) if children.len() == 1 => children.pop(),
+            _ => None,
+        };
+        if let Some(child) = single {
+            *self = child;
+        }
+        removed
+    }
+    // Ids after a removed window move down
+    fn renumber(&mut self, removed: usize) {
+        match *self {
+            Layout::Window(ref mut id) if *id > removed => *id -= 1,
+            Layout::Window(_) => (),
+            Layout::Split(_, ref mut children) => children.iter_mut().for_each(|child| child.renumber(removed)),
+        }
+    }
+    // Shares the area equally, one row or column between windows is left for the separator
+    fn regions(&self, area: Region, windows: &mut Vec<(usize, Region)>, separators: &mut Vec<Region>) {
+        let (direction, children) = match *self {
+            Layout::Window(id) => return windows.push((id, area)),
+            Layout::Split(direction, ref children) => (direction, children),
+        };
+        let n = children.len();
+        let total = match direction {
+            Direction::Horizontal => area.rows,
+            Direction::Vertical => area.width,
+        }
+        .saturating_sub(n - 1);
+        let mut start = 0;
+        for (i, child) in children.iter().enumerate() {
+            let size = total / n + if i < total % n { 1 } else { 0 };
+            let (region, separator) = match direction {
+                Direction::Horizontal => (
+                    Region { top: area.top + start, rows: size, ..area },
+                    Region { top: area.top + start + size, rows: 1, ..area },
+                ),
+                Direction::Vertical => (
+                    Region { left: area.left + start, width: size, ..area },
+                    Region { left: area.left + start + size, width: 1, ..area },
+                ),
+            };
+            child.regions(region, windows, separators);
+            if i + 1 < n {
+                separators.push(separator);
+            }
+            start += size + 1;
+        }
+    }
+}
+
+impl Windows {
+    pub fn new(window: Window) -> Windows {
+        Windows {
+            windows: vec![window],
+            layout: Layout::Window(0),
+            current: 0,
+        }
+    }
+    pub fn current(&self) -> usize {
+        self.current
+    }
+    pub fn get(&self, id: usize) -> Window {
+        self.windows[id]
+    }
+    // Changes a window which is not current, like one scrolled along in diff mode
+    pub fn set(&mut self, id: usize, window: Window) {
+        self.windows[id] = window;
+    }
+    // Splits the current window, the new window shows the same and becomes current
+    pub fn split(&mut self, direction: Direction, current: Window) {
+        self.windows[self.current] = current;
+        let new = self.windows.len();
+        self.windows.push(current);
+        self.layout.split(self.current, new, direction);
+        self.current = new;
+    }
+    // Makes the next or previous window current and returns its state
+    pub fn switch(&mut self, current: Window, forward: bool) -> Window {
+        self.windows[self.current] = current;
+        let mut ids = vec![];
+        self.layout.order(&mut ids);
+        let i = ids.iter().position(|&id| id == self.current).unwrap();
+        let next = if forward { (i + 1) % ids.len() } else { (i + ids.len() - 1) % ids.len() };
+        self.current = ids[next];
+        self.windows[self.current]
+    }
+    // Closes the current window and returns the state of the new current one, the last window stays
+    pub fn close(&mut self) -> Option<Window> {
+        if self.windows.len() == 1 {
+            return None;
+        }
+        let mut ids = vec![];
+        self.layout.order(&mut ids);
+        let i = ids.iter().position(|&id| id == self.current).unwrap();
+        let next = if i > 0 { ids[i - 1] } else { ids[1] };
+        self.layout.remove(self.current);
+        self.layout.renumber(self.current);
+        self.windows.remove(self.current);
+        self.current = if next > self.current { next - 1 } else { next };
+        Some(self.windows[self.current])
+    }
+    // Closes all windows except the current one
+    pub fn only(&mut self, current: Window) {
+        *self = Windows::new(current);
+    }
+    // Regions of the windows in screen order and of the separators between them
+    pub fn regions(&self, area: Region) -> (Vec<(usize, Region)>, Vec<Region>) {
+        let mut windows = vec![];
+        let mut separators = vec![];
+        self.layout.regions(area, &mut windows, &mut separators);
+        (windows, separators)
+    }
+}
+
+pub fn draw_separator(separator: &Region) {
+    if separator.rows == 1 {
+        mvhline(separator.top as i32, separator.left as i32, ACS_HLINE(), separator.width as i32);
+    } else {
+        mvvline(separator.top as i32, separator.left as i32, ACS_VLINE(), separator.rows as i32);
+    }
+}
+
+#[cfg(test)]
+fn window(cursorpos: usize) -> Window {
+    Window {
+        cursorpos,
+        cstate: Cursorstate::Leftnibble,
+        screenoffset: 0,
+        buffer: 0,
+    }
+}
+#[test]
+fn split_shares_the_screen() {
+    let mut windows = Windows::new(window(0));
+    windows.split(Direction::Vertical, window(1));
+    windows.split(Direction::Horizontal, window(2));
+    let area = Region { top: 0, left: 0, rows: 23, width: 161 };
+    let (regions, separators) = windows.regions(area);
+    assert_eq!(regions, [
+        (2, Region { top: 0, left: 0, rows: 11, width: 80 }),
+        (1, Region { top: 12, left: 0, rows: 11, width: 80 }),
+        (0, Region { top: 0, left: 81, rows: 23, width: 80 }),
+    ]);
+    assert_eq!(separators, [
+        Region { top: 11, left: 0, rows: 1, width: 80 },
+        Region { top: 0, left: 80, rows: 23, width: 1 },
+    ]);
+}
+#[test]
+fn switch_and_close_keep_states() {
+    let mut windows = Windows::new(window(0));
+    windows.split(Direction::Horizontal, window(5));
+    assert_eq!(windows.switch(window(7), true), window(5));
+    assert_eq!(windows.switch(window(5), false), window(7));
+    assert_eq!(windows.close(), Some(window(5)));
+    assert_eq!(windows.close(), None);
+}